@@ -1,2 +1,5 @@
 pub mod lazy;
-pub(crate) mod storage;
+pub mod migrate;
+pub mod schema;
+pub mod storage;
+pub mod tree;
@@ -1,8 +1,9 @@
 use super::storage::*;
+use super::tree::{self, Parents, Children};
 use std::marker::PhantomData;
-use crate::hlist::{HList, Append, Nil, Cons};
-use crate::versions::Version;
-use crate::path::{Path, Chain};
+use crate::hlist::{HList, Append, Nil, Cons, AllOk};
+use crate::versions::{Version, LoadError, Opaque, Counter, LastVersionRef};
+use crate::path::{Path, Chain, ParentOf};
 use crate::versions;
 
 impl<'db, T: Database<'db>> DatabaseExt<'db> for T {}
@@ -53,7 +54,7 @@ impl<Txn, P, R, L> ExecuteMany<Txn> for Cons<(P, R), L> where
 }
 
 impl<'db, Db, Txn, R> AccessMany<'db, Db, Txn, R> {
-    fn access<P: Chain>(self, path: P) -> Access<'db, Db, P, Txn, Nil, R> {
+    pub fn access<P: Chain>(self, path: P) -> Access<'db, Db, P, Txn, Nil, R> {
         Access {
             path,
             result: Nil,
@@ -61,13 +62,18 @@ impl<'db, Db, Txn, R> AccessMany<'db, Db, Txn, R> {
         }
     }
 
-    fn execute(self) -> R::Result where
+    /// Runs everything in single transaction.
+    /// Write transaction is committed only when every operation succeeded and aborted otherwise.
+    pub fn execute(self) -> Result<R::Result, TxnError<Db::TxnErr, Txn::CommitErr>> where
         R: ExecuteMany<Txn>,
+        R::Result: AllOk,
         Db: Database<'db>,
         Txn: Corresponds<'db, Db>,
     {
-        let mut txn = Txn::create(self.db);
-        self.result.execute(&mut txn)
+        let mut txn = Txn::create(self.db).map_err(TxnError::Begin)?;
+        let res = self.result.execute(&mut txn);
+        txn.finish(res.all_ok()).map_err(TxnError::Commit)?;
+        Ok(res)
     }
 }
 
@@ -113,22 +119,47 @@ pub trait AccessImpl<'db, Db: Database<'db>, P: Chain, Txn: Corresponds<'db, Db>
 }
 
 // Ensure that AccessImpl::*Txn corresponds to Db generic parameter
-pub trait Corresponds<'db, Db: Database<'db>> {
-    fn create(db: &'db Db) -> Self;
+pub trait Corresponds<'db, Db: Database<'db>>: Sized {
+    fn create(db: &'db Db) -> Result<Self, Db::TxnErr>;
+
+    type CommitErr;
+    /// Commits transaction when `success` is true and aborts it otherwise
+    fn finish(self, success: bool) -> Result<(), Self::CommitErr>;
 }
 impl<'db, Db: Database<'db>> Corresponds<'db, Db> for Ro<'db, Db> {
-    fn create(db: &'db Db) -> Self {
-        Ro(db.ro())
+    fn create(db: &'db Db) -> Result<Self, Db::TxnErr> {
+        db.ro().map(Ro)
+    }
+
+    // Nothing to commit, so just drop it
+    type CommitErr = !;
+    fn finish(self, _success: bool) -> Result<(), !> {
+        Ok(())
     }
 }
 impl<'db, Db: Database<'db>> Corresponds<'db, Db> for Rw<'db, Db> {
-    fn create(db: &'db Db) -> Self {
-        Rw(db.rw())
+    fn create(db: &'db Db) -> Result<Self, Db::TxnErr> {
+        db.rw().map(Rw)
+    }
+
+    type CommitErr = <Self as CanWrite>::CommitErr;
+    fn finish(self, success: bool) -> Result<(), Self::CommitErr> {
+        if success {
+            self.commit()
+        } else {
+            self.abort();
+            Ok(())
+        }
     }
 }
 impl<'db, Db: Database<'db>> Corresponds<'db, Db> for NoTxn {
-    fn create(_db: &'db Db) -> Self {
-        NoTxn
+    fn create(_db: &'db Db) -> Result<Self, Db::TxnErr> {
+        Ok(NoTxn)
+    }
+
+    type CommitErr = !;
+    fn finish(self, _success: bool) -> Result<(), !> {
+        Ok(())
     }
 }
 
@@ -190,32 +221,128 @@ macro_rules! returns {
     };
 }
 
+fn load_error<E>(e: LoadError) -> GetError<E> {
+    match e {
+        LoadError::Load(e) => GetError::DeserializationError(e),
+        e => GetError::DeserializationError(Box::new(e)),
+    }
+}
+
 lazy!(
     pub LazyGet<V> where (V: Version + versions::Serde) { phantom: PhantomData<V> }
     Path=path
     txn=Txn: CanRead
     | | -> Result<V, GetError<Txn::GetErr>> {
         let data = txn.get(Storage::Data, path)?;
-        V::load(data).map_err(GetError::DeserializationError)
+        versions::load_versioned(data).map_err(load_error)
+    }
+);
+
+lazy!(
+    pub LazyGetOpaque<V> where (V: Version + versions::Serde) { phantom: PhantomData<V> }
+    Path=path
+    txn=Txn: CanRead
+    | | -> Result<Opaque<V>, GetError<Txn::GetErr>> {
+        let data = txn.get(Storage::Data, path)?;
+        Opaque::load_versioned(data).map_err(load_error)
+    }
+);
+
+lazy!(
+    pub LazyGetAndUpgrade<V> where (V: Version + versions::Serde + Clone) { phantom: PhantomData<V> }
+    Path=path
+    txn=Txn: CanWrite
+    | | -> Result<V, UpgradeError<Txn::GetErr, Txn::SetErr>> {
+        let data = txn.get(Storage::Data, path).map_err(UpgradeError::Get)?;
+        let (version, payload) = versions::split_version(data)
+            .map_err(|e| UpgradeError::Get(load_error(e)))?;
+        let val: V = versions::load(version, payload)
+            .map_err(|e| UpgradeError::Get(load_error(e)))?;
+        // Never write back downgraded values, newer version may still need them
+        if version < V::VERSION {
+            let data = versions::save_versioned(val.clone())
+                .map_err(|e| UpgradeError::Set(SetError::SerializationError(e)))?;
+            txn.set(Storage::Data, path, &data).map_err(UpgradeError::Set)?;
+        }
+        Ok(val)
     }
 );
 
 lazy!(
-    pub LazySet<V> where (V: Version + versions::Serde) {}
+    pub LazySet<V> where (
+        V: Version + versions::Serde,
+        Txn::ExistsErr: Into<Txn::SetErr>,
+        Txn::GetErr: Into<Txn::SetErr>,
+    ) {}
     Path=path
     txn=Txn: CanWrite
-    | val: V | -> Result<(), SetError<Txn::SetErr>> {
-        let data = val.save().map_err(SetError::SerializationError)?;
+    | val: V, parents: Parents | -> Result<(), SetError<Txn::SetErr>> {
+        let data = versions::save_versioned(val).map_err(SetError::SerializationError)?;
+        // Newer version of the program may have written something we can't read
+        match txn.get(Storage::Data, path) {
+            Ok(old) => {
+                let (version, _) = versions::split_version(old)
+                    .map_err(|e| SetError::SerializationError(Box::new(e)))?;
+                let max = <V as LastVersionRef>::LastVersion::VERSION;
+                if version > max {
+                    return Err(SetError::VersionTooBig { version, max })
+                }
+            },
+            Err(GetError::NoSuchPath) => {},
+            Err(GetError::DeserializationError(e)) => return Err(SetError::SerializationError(e)),
+            Err(GetError::Other(e)) => return Err(SetError::Other(e.into())),
+        }
+        tree::create(txn, path, parents)?;
         txn.set(Storage::Data, path, &data)
     }
 );
 
 lazy!(
-    pub LazyRemove<> where () {}
+    pub LazyChildren<C> where (C: Path) { phantom: PhantomData<C> }
+    Path=path
+    txn=Txn: CanRead
+    | | -> Result<Children<C>, GetError<Txn::GetErr>> {
+        match tree::children(txn, path)? {
+            Some(info) => Ok(Children::decode(&info)),
+            // Root without children
+            None if tree::parent(path).is_none() => Ok(Children::default()),
+            None => Err(GetError::NoSuchPath),
+        }
+    }
+);
+
+lazy!(
+    pub LazyRemove<> where (
+        Txn::ExistsErr: Into<Txn::RemoveErr>,
+        Txn::GetErr: Into<Txn::RemoveErr>,
+        Txn::SetErr: Into<Txn::RemoveErr>,
+    ) {}
     Path=path
     txn=Txn: CanWrite
     | | -> Result<(), RemoveError<Txn::RemoveErr>> {
-        txn.remove(Storage::Data, path)
+        match txn.remove(Storage::Data, path) {
+            Ok(()) => {},
+            // Ancestors created by `Parents::Create` have no data, but they are still nodes
+            Err(RemoveError::NoSuchPath) if tree::parent(path).is_some()
+                && txn.exists(Storage::Children, path).map_err(|e| RemoveError::Other(e.into()))? => {},
+            Err(e) => return Err(e),
+        }
+        // Node stays in the tree while it has children
+        tree::remove_empty(txn, path)?;
+        Ok(())
+    }
+);
+
+lazy!(
+    pub LazyRemoveRecursive<> where (
+        Txn::GetErr: Into<Txn::RemoveErr>,
+        Txn::SetErr: Into<Txn::RemoveErr>,
+        Txn::IterErr: Into<Txn::RemoveErr>,
+    ) {}
+    Path=path
+    txn=Txn: CanWrite
+    | | -> Result<usize, RemoveError<Txn::RemoveErr>> {
+        tree::remove_recursive(txn, path)
     }
 );
 
@@ -235,21 +362,71 @@ impl<'db, Db, P, Txn, R, ParentRes> Access<'db, Db, P, Txn, R, ParentRes> where
         })
     }
 
+    /// Same as `get`, but values of unknown (newer) versions are returned as is instead of failing
+    pub fn get_opaque<V>(self) -> returns!(RoTxn => LazyGetOpaque<V>) where
+        R: Append<LazyGetOpaque<V>>,
+        V: Version<FirstVersion=<<P as Chain>::Last as Path>::AssociatedData> + versions::Serde
+    {
+        returns!(self => LazyGetOpaque {
+            phantom: PhantomData::default()
+        })
+    }
+
+    /// Same as `get`, but when value was upgraded, saves it back, so next reads will be cheaper
+    pub fn get_and_upgrade<V>(self) -> returns!(RwTxn => LazyGetAndUpgrade<V>) where
+        R: Append<LazyGetAndUpgrade<V>>,
+        V: Version<FirstVersion=<<P as Chain>::Last as Path>::AssociatedData> + versions::Serde + Clone
+    {
+        returns!(self => LazyGetAndUpgrade {
+            phantom: PhantomData::default()
+        })
+    }
+
+    /// Sets value of existing node or creates new one. Parent must exist.
+    /// Values saved by newer versions of the program are never overwritten.
     pub fn set<V>(self, val: V) -> returns!(RwTxn => LazySet<V>) where
         R: Append<LazySet<V>>,
         V: Version<FirstVersion=<<P as Chain>::Last as Path>::AssociatedData> + versions::Serde
+    {
+        self.set_with(val, Parents::Require)
+    }
+
+    pub fn set_with<V>(self, val: V, parents: Parents) -> returns!(RwTxn => LazySet<V>) where
+        R: Append<LazySet<V>>,
+        V: Version<FirstVersion=<<P as Chain>::Last as Path>::AssociatedData> + versions::Serde
     {
         returns!(self => LazySet {
-            val
+            val,
+            parents
         })
     }
 
+    /// Lists children of type `C`. Other children are reported in `Children::skipped`
+    pub fn children<C>(self) -> returns!(RoTxn => LazyChildren<C>) where
+        R: Append<LazyChildren<C>>,
+        C: Path,
+        <P as Chain>::Last: ParentOf<C>,
+    {
+        returns!(self => LazyChildren {
+            phantom: PhantomData::default()
+        })
+    }
+
+    /// Removes value of the node. Node itself is removed too unless it has children.
+    /// Works for nodes without value as well, e.g. for ones created by `Parents::Create`
     pub fn remove(self) -> returns!(RwTxn => LazyRemove)
         where R: Append<LazyRemove>
     {
         returns!(self => LazyRemove {})
     }
 
+    /// Removes node with the whole subtree. Returns number of removed nodes
+    pub fn remove_recursive(self) -> returns!(RwTxn => LazyRemoveRecursive)
+        where R: Append<LazyRemoveRecursive>
+    {
+        returns!(self => LazyRemoveRecursive {})
+    }
+
     pub fn done(self) -> AccessMany<'db, Db, Txn, <ParentRes as Append<(P, R)>>::Result>
         where ParentRes: Append<(P, R)>
     {
@@ -266,9 +443,11 @@ impl<'db, Db, P, Txn, R, ParentRes> Access<'db, Db, P, Txn, R, ParentRes> where
         self.done().access(path)
     }
 
-    pub fn execute(self) -> <<ParentRes as Append<(P, R)>>::Result as ExecuteMany<Txn>>::Result where
+    #[allow(clippy::type_complexity)]
+    pub fn execute(self) -> Result<<<ParentRes as Append<(P, R)>>::Result as ExecuteMany<Txn>>::Result, TxnError<Db::TxnErr, Txn::CommitErr>> where
         ParentRes: Append<(P, R)>,
         <ParentRes as Append<(P, R)>>::Result: ExecuteMany<Txn>,
+        <<ParentRes as Append<(P, R)>>::Result as ExecuteMany<Txn>>::Result: AllOk,
         Db: Database<'db>,
         Txn: Corresponds<'db, Db>,
     {
@@ -315,6 +494,9 @@ mod tests {
     use super::*;
     use crate::path::Root;
     use crate::storage::testdb::PanicDb;
+    use crate::storage::memory::MemoryDb;
+    use crate::hlist::Unpack;
+    use serde::{Serialize, Deserialize};
     extern crate static_assertions as sa;
 
     // Unfortunately static_assertions does not support generics
@@ -363,14 +545,237 @@ mod tests {
                 .get::<String>()
                 .remove()
             .execute()
+            .unwrap()
+            .unwrap_all();
+    }
+
+    #[test]
+    fn commit_on_success() {
+        let db = MemoryDb::new();
+        let _: HList![HList![()]] = db.lazy()
+            .access(hlist![Root, Foo])
+                .set(42_i32)
+            .execute()
+            .unwrap()
+            .unwrap_all();
+
+        let got: HList![HList![i32]] = db.lazy()
+            .access(hlist![Root, Foo])
+                .get::<i32>()
+            .execute()
+            .unwrap()
+            .unwrap_all();
+        assert_eq!((got.0).0, 42);
+    }
+
+    #[test]
+    fn abort_on_failure() {
+        let db = MemoryDb::new();
+        let (_, removed) = db.lazy()
+            .access(hlist![Root, Foo])
+                .set(42_i32)
+            .access(hlist![Root, Bar])
+                .remove()
+            .execute()
+            .unwrap()
+            .unpack();
+        // Bar does not exist, so the whole transaction is aborted
+        assert!(matches!(removed.0, Err(RemoveError::NoSuchPath)));
+
+        let (got,) = db.lazy()
+            .access(hlist![Root, Foo])
+                .get::<i32>()
+            .execute()
+            .unwrap()
+            .unpack();
+        assert!(matches!(got.0, Err(GetError::NoSuchPath)));
+    }
+
+    #[test]
+    fn parent_required() {
+        let db = MemoryDb::new();
+        let (baz,) = db.lazy()
+            .access(hlist![Root, Foo, Baz])
+                .set(1_u8)
+            .execute()
+            .unwrap()
+            .unpack();
+        assert!(matches!(baz.0, Err(SetError::NoParentExists)));
+
+        let _: HList![HList![()], HList![()]] = db.lazy()
+            .access(hlist![Root, Foo, Baz])
+                .set_with(1_u8, Parents::Create)
+            // Foo is created now, so it is not required to use Parents::Create
+            .access(hlist![Root, Foo, Qux])
+                .set(2_u8)
+            .execute()
+            .unwrap()
+            .unwrap_all();
+    }
+
+    #[test]
+    fn remove_created_parent() {
+        let db = MemoryDb::new();
+        let (_, _, _, root) = db.lazy()
+            .access(hlist![Root, Foo, Baz])
+                .set_with(1_u8, Parents::Create)
+            .access(hlist![Root, Foo, Baz])
+                .remove()
+            // Foo has no value, but it is removed as well
+            .access(hlist![Root, Foo])
+                .remove()
+            .access(hlist![Root])
+                .children::<Foo>()
+            .execute()
+            .unwrap()
+            .unwrap_all()
+            .unpack();
+        assert_eq!(root.0.total_count, 0);
+
+        let (foo,) = db.lazy()
+            .access(hlist![Root, Foo])
+                .remove()
+            .execute()
+            .unwrap()
+            .unpack();
+        assert!(matches!(foo.0, Err(RemoveError::NoSuchPath)));
+    }
+
+    #[test]
+    fn children() {
+        let db = MemoryDb::new();
+        let _: HList![HList![()], HList![()], HList![()]] = db.lazy()
+            .access(hlist![Root, Foo])
+                .set(0_i32)
+            .access(hlist![Root, Foo, Baz])
+                .set(1_u8)
+            .access(hlist![Root, Foo, Qux])
+                .set(2_u8)
+            .execute()
+            .unwrap()
             .unwrap_all();
+
+        let (foo, root) = db.lazy()
+            .access(hlist![Root, Foo])
+                .children::<Baz>()
+            .access(hlist![Root])
+                .children::<Bar>()
+            .execute()
+            .unwrap()
+            .unwrap_all()
+            .unpack();
+        let foo = foo.0;
+        assert_eq!(foo.items, vec![Baz]);
+        assert_eq!(foo.skipped.len(), 1);  // Qux
+        assert_eq!(foo.total_count, 2);
+
+        // Root has only Foo
+        let root = root.0;
+        assert!(root.items.is_empty());
+        assert_eq!(root.total_count, 1);
+    }
+
+    #[test]
+    fn remove_recursive() {
+        let db = MemoryDb::new();
+        let (_, removed, children) = db.lazy()
+            .access(hlist![Root, Foo, Baz])
+                .set_with(1_u8, Parents::Create)
+            .access(hlist![Root, Foo])
+                .remove_recursive()
+            .access(hlist![Root])
+                .children::<Foo>()
+            .execute()
+            .unwrap()
+            .unwrap_all()
+            .unpack();
+        assert_eq!(removed.0, 2);  // Foo and Baz
+        assert_eq!(children.0.total_count, 0);
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct OldNum(u8);
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct NewNum(u16);
+    chain!(#auto OldNum => #auto NewNum);
+    impl versions::Upgradeable for NewNum {
+        fn upgrade(prev: OldNum) -> Result<Self, Box<dyn std::error::Error>> {
+            Ok(Self(prev.0.into()))
+        }
+    }
+
+    #[test]
+    fn get_and_upgrade() {
+        let db = MemoryDb::new();
+        let (_, upgraded) = db.lazy()
+            .access(hlist![Root, Num])
+                .set(OldNum(42))
+            .access(hlist![Root, Num])
+                .get_and_upgrade::<NewNum>()
+            .execute()
+            .unwrap()
+            .unwrap_all()
+            .unpack();
+        assert_eq!(upgraded.0, NewNum(42));
+
+        // NewNum can't be downgraded, so stored value is really upgraded
+        let (old,) = db.lazy()
+            .access(hlist![Root, Num])
+                .get::<OldNum>()
+            .execute()
+            .unwrap()
+            .unpack();
+        assert!(matches!(old.0, Err(GetError::DeserializationError(_))));
+    }
+
+    #[test]
+    fn newer_version() {
+        let db = MemoryDb::new();
+        db.lazy()
+            .access(hlist![Root, Num])
+                .set(OldNum(42))
+            .execute()
+            .unwrap();
+
+        // Pretend that value is saved by the program that knows about third version
+        let newer = Opaque::<OldNum>::Unknown { version: 2, data: vec![1, 2, 3] };
+        let mut txn = db.rw().unwrap();
+        let path = Chain::collect(hlist![Root, Num]).into_bytes();
+        txn.set(Storage::Data, &path, &newer.clone().save_versioned().unwrap()).unwrap();
+        txn.commit().unwrap();
+
+        let (num,) = db.lazy()
+            .access(hlist![Root, Num])
+                .get_opaque::<NewNum>()
+                .set(NewNum(1))
+            .execute()
+            .unwrap()
+            .unpack();
+        assert!(matches!(num.0, Ok(Opaque::Unknown { version: 2, .. })));
+        assert!(matches!((num.1).0, Err(SetError::VersionTooBig { version: 2, max: 1 })));
+
+        // Nothing is overwritten
+        let (got,) = db.lazy()
+            .access(hlist![Root, Num])
+                .get_opaque::<OldNum>()
+            .execute()
+            .unwrap()
+            .unpack();
+        assert_eq!(got.0.unwrap(), newer);
     }
 
     path!(
         struct Foo[i32];
         struct Bar[String];
+        struct Num[OldNum];
+        struct Baz[u8];
+        struct Qux[u8];
     );
-    path!(Root -> {Foo}
+    path!(Root -> {Foo
+                    -> {Baz}
+                    -> {Qux}
+                  }
                -> {Bar}
+               -> {Num}
          );
 }
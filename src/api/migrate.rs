@@ -0,0 +1,171 @@
+//! Bulk migration of stored values to the latest version of their chain
+use super::storage::*;
+use super::tree;
+use crate::path::{Chain, Path};
+use crate::versions::{self, Serde, LastVersionRef, LoadError};
+use std::collections::BTreeMap;
+use std::num::NonZeroUsize;
+use std::ops::Bound;
+
+#[derive(Debug, Default)]
+pub struct Report {
+    /// How many values of each version were found (before migration)
+    pub versions: BTreeMap<usize, usize>,
+    /// How many values were rewritten
+    pub migrated: usize,
+    /// Values that can't be migrated, by their paths
+    pub errors: Vec<(Vec<u8>, LoadError)>,
+}
+
+#[derive(Debug)]
+pub enum MigrateError<B, C, I, S> {
+    Txn(TxnError<B, C>),
+    Iter(I),
+    Set(SetError<S>),
+}
+
+type Latest<C> = <<C as Path>::AssociatedData as LastVersionRef>::LastVersion;
+
+#[allow(clippy::type_complexity)]
+type Error<'db, Db> = MigrateError<
+    <Db as Database<'db>>::TxnErr,
+    <<Db as Database<'db>>::RwTxn as CanWrite>::CommitErr,
+    <<Db as Database<'db>>::RwTxn as CanRead>::IterErr,
+    <<Db as Database<'db>>::RwTxn as CanWrite>::SetErr,
+>;
+
+/// Returns stored version and new data, when value has to be rewritten
+fn upgrade<L: Serde>(data: Vec<u8>) -> Result<(usize, Option<Vec<u8>>), LoadError> {
+    let (version, payload) = versions::split_version(data)?;
+    if version == L::VERSION {
+        return Ok((version, None))
+    }
+    let val = versions::load::<L>(version, payload)?;
+    let data = versions::save_versioned(val).map_err(LoadError::Migration)?;
+    Ok((version, Some(data)))
+}
+
+/// Migrates every value of `C` nodes under `prefix` (including itself) to the latest version.
+///
+/// Node is treated as `C` when its last segment is decoded by `C::from_segment`.
+/// Ancestors are not checked, so `C` may be placed at any depth and under any parents.
+///
+/// Each transaction looks at no more than `batch_size` values, so long migrations don't block writers.
+/// Values that can't be loaded are left untouched and reported.
+pub fn migrate<'db, Db, P, C>(db: &'db Db, prefix: P, batch_size: NonZeroUsize) -> Result<Report, Error<'db, Db>> where
+    Db: Database<'db>,
+    P: Chain,
+    C: Path,
+    Latest<C>: Serde,
+{
+    let batch_size = batch_size.get();
+    let prefix = Chain::collect(prefix).into_bytes();
+    let end = prefix_end(&prefix);
+
+    let mut report = Report::default();
+    // Last key of the previous batch
+    let mut last: Option<Vec<u8>> = None;
+    loop {
+        let mut txn = db.rw().map_err(|e| MigrateError::Txn(TxnError::Begin(e)))?;
+        let start = last.as_deref().map_or(Bound::Included(&prefix[..]), Bound::Excluded);
+        let stop = end.as_deref().map_or(Bound::Unbounded, Bound::Excluded);
+        let batch = txn.range(Storage::Data, start, stop, Direction::Forward)
+            .map_err(MigrateError::Iter)?
+            .take(batch_size)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(MigrateError::Iter)?;
+        let finished = batch.len() < batch_size;
+
+        for (key, data) in batch {
+            let matches = tree::segment(&key).map_or(false, |seg| C::from_segment(seg).is_ok());
+            if matches {
+                match upgrade::<Latest<C>>(data) {
+                    Ok((version, new)) => {
+                        *report.versions.entry(version).or_default() += 1;
+                        if let Some(new) = new {
+                            txn.set(Storage::Data, &key, &new).map_err(MigrateError::Set)?;
+                            report.migrated += 1;
+                        }
+                    },
+                    Err(e) => report.errors.push((key.clone(), e)),
+                }
+            }
+            last = Some(key);
+        }
+
+        txn.commit().map_err(|e| MigrateError::Txn(TxnError::Commit(e)))?;
+        if finished {
+            return Ok(report)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::lazy::DatabaseExt;
+    use crate::api::tree::Parents;
+    use crate::hlist::{Unpack, UnwrapAll};
+    use crate::path::Root;
+    use crate::storage::memory::MemoryDb;
+    use serde::{Serialize, Deserialize};
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct V1(u8);
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct V2(u16);
+    chain!(#auto V1 => #auto V2);
+    impl versions::Upgradeable for V2 {
+        fn upgrade(prev: V1) -> Result<Self, Box<dyn std::error::Error>> {
+            Ok(Self(u16::from(prev.0) * 2))
+        }
+    }
+
+    path!(
+        struct A;
+        struct B;
+        struct Item[V1];
+        struct Other[i32];
+    );
+    path!(Root -> {A -> {Item}}
+               -> {B -> {Item}
+                     -> {Other}}
+         );
+
+    #[test]
+    fn migrate_all() {
+        let db = MemoryDb::new();
+        let _: HList![HList![()], HList![()], HList![()]] = db.lazy()
+            .access(hlist![Root, A, Item])
+                .set_with(V1(1), Parents::Create)
+            .access(hlist![Root, B, Item])
+                .set_with(V2(2), Parents::Create)
+            .access(hlist![Root, B, Other])
+                .set(3_i32)
+            .execute()
+            .unwrap()
+            .unwrap_all();
+
+        // Batch of one value, so there will be several transactions
+        let report = migrate::<_, _, Item>(&db, hlist![Root], NonZeroUsize::new(1).unwrap()).unwrap();
+        assert!(report.errors.is_empty());
+        assert_eq!(report.migrated, 1);
+        assert_eq!(report.versions.into_iter().collect::<Vec<_>>(), vec![(0, 1), (1, 1)]);
+
+        let (a, b) = db.lazy()
+            .access(hlist![Root, A, Item])
+                .get::<V2>()
+            .access(hlist![Root, B, Item])
+                .get::<V2>()
+            .execute()
+            .unwrap()
+            .unwrap_all()
+            .unpack();
+        assert_eq!(a.0, V2(2));
+        assert_eq!(b.0, V2(2));
+
+        // Nothing to do now
+        let report = migrate::<_, _, Item>(&db, hlist![Root, A], NonZeroUsize::new(10).unwrap()).unwrap();
+        assert_eq!(report.migrated, 0);
+    }
+}
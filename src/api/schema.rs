@@ -0,0 +1,269 @@
+//! Registry of chains attached to paths, stored in `Storage::Meta`.
+//!
+//! Chains and paths exist only at compile time, so database can't know what it contains.
+//! Registry remembers the latest version ever written for each path,
+//! so older binary can find out that it is too old before reading anything.
+//! Paths are recorded under names given by the program, since type names are neither unique nor stable.
+use super::storage::*;
+use crate::path::Path;
+use crate::versions::{Counter, LastVersionRef};
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+
+const REGISTRY_KEY: &[u8] = b"schema";
+
+/// Chain attached to some path
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChainInfo {
+    /// `TYPE_NAME` of the first version. Give it a stable name in `chain!`, so moving the type won't change it
+    pub name: String,
+    /// Version of the last element
+    pub version: usize,
+}
+
+/// Chains of all paths known to the program, by names of the paths
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Schema {
+    chains: BTreeMap<String, ChainInfo>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Incompatibility {
+    /// Database may contain values that are newer than program knows
+    VersionTooBig {
+        path: String,
+        version: usize,
+        max: usize,
+    },
+    /// Other chain was attached to the path before
+    ChainChanged {
+        path: String,
+        stored: String,
+        current: String,
+    },
+}
+
+/// What to do when database is incompatible with the schema
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnIncompatible {
+    /// Fail with `SchemaError::Incompatible`
+    Refuse,
+    /// Just return found incompatibilities. Stored registry is never downgraded
+    Warn,
+}
+
+#[derive(Debug)]
+pub enum SchemaError<B, C, G, S> {
+    Txn(TxnError<B, C>),
+    Get(GetError<G>),
+    Set(SetError<S>),
+    Incompatible(Vec<Incompatibility>),
+}
+
+fn load(data: Vec<u8>) -> Result<BTreeMap<String, ChainInfo>, Box<dyn Error>> {
+    let mut cur = std::io::Cursor::new(data);
+    let val = rmpv::decode::read_value(&mut cur)?;
+    let res = rmpv::ext::from_value(val)?;
+    Ok(res)
+}
+
+fn save(chains: &BTreeMap<String, ChainInfo>) -> Result<Vec<u8>, Box<dyn Error>> {
+    let val = rmpv::ext::to_value(chains)?;
+    let mut buf = Vec::new();
+    rmpv::encode::write_value(&mut buf, &val)?;
+    Ok(buf)
+}
+
+fn read<T: CanRead>(txn: &T) -> Result<BTreeMap<String, ChainInfo>, GetError<T::GetErr>> {
+    match txn.get(Storage::Meta, REGISTRY_KEY) {
+        Ok(data) => load(data).map_err(GetError::DeserializationError),
+        // Nothing was registered yet
+        Err(GetError::NoSuchPath) => Ok(BTreeMap::new()),
+        Err(e) => Err(e),
+    }
+}
+
+impl Schema {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds path and its chain to the schema under `name`, like `"users.doc"`.
+    /// Name identifies the path in the database, so it must never change.
+    /// # Panics
+    /// When `name` is already used by other path
+    #[must_use]
+    pub fn path<P: Path>(mut self, name: &str) -> Self {
+        let info = ChainInfo {
+            name: <P::AssociatedData as Counter>::TYPE_NAME.to_string(),
+            version: <P::AssociatedData as LastVersionRef>::LastVersion::VERSION,
+        };
+        let old = self.chains.insert(name.to_string(), info);
+        assert!(old.is_none(), "path {:?} is added to the schema twice", name);
+        self
+    }
+
+    /// Compares schema with the stored registry
+    #[must_use]
+    pub fn compare(&self, stored: &BTreeMap<String, ChainInfo>) -> Vec<Incompatibility> {
+        let mut res = Vec::new();
+        for (path, current) in &self.chains {
+            let stored = match stored.get(path) {
+                Some(stored) => stored,
+                None => continue,
+            };
+            if stored.name != current.name {
+                res.push(Incompatibility::ChainChanged {
+                    path: path.clone(),
+                    stored: stored.name.clone(),
+                    current: current.name.clone(),
+                });
+            } else if stored.version > current.version {
+                res.push(Incompatibility::VersionTooBig {
+                    path: path.clone(),
+                    version: stored.version,
+                    max: current.version,
+                });
+            }
+        }
+        res
+    }
+
+    /// Compares schema with the registry without modifying it
+    #[allow(clippy::type_complexity)]
+    pub fn check<'db, Db: Database<'db>>(&self, db: &'db Db, on_incompatible: OnIncompatible)
+        -> Result<Vec<Incompatibility>, SchemaError<Db::TxnErr, !, <Db::RoTxn as CanRead>::GetErr, !>>
+    {
+        let txn = db.ro().map_err(|e| SchemaError::Txn(TxnError::Begin(e)))?;
+        let stored = read(&txn).map_err(SchemaError::Get)?;
+        let found = self.compare(&stored);
+        match on_incompatible {
+            OnIncompatible::Refuse if !found.is_empty() => Err(SchemaError::Incompatible(found)),
+            _ => Ok(found),
+        }
+    }
+
+    /// Compares schema with the registry and records it there.
+    /// Incompatible entries are left as is.
+    #[allow(clippy::type_complexity)]
+    pub fn apply<'db, Db: Database<'db>>(&self, db: &'db Db, on_incompatible: OnIncompatible) -> Result<
+        Vec<Incompatibility>,
+        SchemaError<
+            Db::TxnErr,
+            <Db::RwTxn as CanWrite>::CommitErr,
+            <Db::RwTxn as CanRead>::GetErr,
+            <Db::RwTxn as CanWrite>::SetErr
+        >
+    > {
+        let mut txn = db.rw().map_err(|e| SchemaError::Txn(TxnError::Begin(e)))?;
+        let mut stored = read(&txn).map_err(SchemaError::Get)?;
+        let found = self.compare(&stored);
+        if on_incompatible == OnIncompatible::Refuse && !found.is_empty() {
+            txn.abort();
+            return Err(SchemaError::Incompatible(found))
+        }
+
+        let mut changed = false;
+        for (path, current) in &self.chains {
+            let entry = stored.entry(path.clone()).or_insert_with(|| {
+                changed = true;
+                current.clone()
+            });
+            if entry.name == current.name && entry.version < current.version {
+                entry.version = current.version;
+                changed = true;
+            }
+        }
+        if changed {
+            let data = save(&stored).map_err(|e| SchemaError::Set(SetError::SerializationError(e)))?;
+            txn.set(Storage::Meta, REGISTRY_KEY, &data).map_err(SchemaError::Set)?;
+        }
+        txn.commit().map_err(|e| SchemaError::Txn(TxnError::Commit(e)))?;
+        Ok(found)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::memory::MemoryDb;
+
+    // Same chain as seen by the old binary, which knows only the first version
+    #[derive(Debug)] struct OldV1;
+    chain!(OldV1 as "test.item.v1");
+
+    #[derive(Debug)] struct V1;
+    #[derive(Debug)] struct V2;
+    chain!(V1 as "test.item.v1" => V2 as "test.item.v2");
+
+    path!(
+        struct Old[OldV1];
+        struct New[V1];
+        struct Other[i32];
+    );
+
+    fn old() -> Schema {
+        Schema::new().path::<Old>("item")
+    }
+
+    fn new() -> Schema {
+        Schema::new().path::<New>("item")
+    }
+
+    #[test]
+    fn version_too_big() {
+        let db = MemoryDb::new();
+        assert!(old().apply(&db, OnIncompatible::Refuse).unwrap().is_empty());
+        assert!(new().apply(&db, OnIncompatible::Refuse).unwrap().is_empty());
+
+        // Old binary can't be used anymore
+        let err = old().check(&db, OnIncompatible::Refuse).unwrap_err();
+        assert!(matches!(err, SchemaError::Incompatible(ref found) if found == &vec![
+            Incompatibility::VersionTooBig {
+                path: "item".to_string(),
+                version: 1,
+                max: 0,
+            }
+        ]));
+
+        // But may be forced to. Registry is not downgraded
+        assert_eq!(old().apply(&db, OnIncompatible::Warn).unwrap().len(), 1);
+        assert_eq!(old().check(&db, OnIncompatible::Warn).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn chain_changed() {
+        let db = MemoryDb::new();
+        Schema::new().path::<Other>("item").apply(&db, OnIncompatible::Refuse).unwrap();
+
+        let found = new().check(&db, OnIncompatible::Warn).unwrap();
+        assert!(matches!(found[..], [Incompatibility::ChainChanged { .. }]));
+    }
+
+    mod users {
+        path!(pub struct Item[i32];);
+    }
+    mod docs {
+        use super::V1;
+        path!(pub struct Item[V1];);
+    }
+
+    #[test]
+    fn same_type_names() {
+        let db = MemoryDb::new();
+        // Both paths are called `Item`, but they are recorded under their own names
+        let schema = Schema::new()
+            .path::<users::Item>("users.item")
+            .path::<docs::Item>("docs.item");
+        assert!(schema.apply(&db, OnIncompatible::Refuse).unwrap().is_empty());
+        assert!(schema.check(&db, OnIncompatible::Refuse).unwrap().is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "added to the schema twice")]
+    fn duplicate_name() {
+        let _ = Schema::new().path::<Old>("item").path::<New>("item");
+    }
+}
@@ -1,15 +1,29 @@
+use std::ops::Bound;
+use crate::reservoir::Size;
+
 #[derive(Debug, Clone, Copy)]
 #[repr(u8)]
 pub enum Storage {
     Data,
     Children,
+    /// Reserved for database itself, e.g. for schema registry
+    Meta,
 }
 
 pub trait Database<'db>: Sized {
     type RoTxn: CanRead + 'db;
     type RwTxn: CanWrite + 'db;
-    fn ro(&'db self) -> Self::RoTxn;
-    fn rw(&'db self) -> Self::RwTxn;
+    /// Error when it is impossible to begin new transaction
+    type TxnErr;
+    fn ro(&'db self) -> Result<Self::RoTxn, Self::TxnErr>;
+    fn rw(&'db self) -> Result<Self::RwTxn, Self::TxnErr>;
+}
+
+/// Failure of the transaction itself, not of some operation inside it
+#[derive(Debug)]
+pub enum TxnError<B, C> {
+    Begin(B),
+    Commit(C),
 }
 
 #[derive(Debug)]
@@ -19,23 +33,77 @@ pub enum GetError<T> {
     Other(T)
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Forward,
+    /// Not every backend has reverse cursors. `HeedDb` finds each previous key with a few seeks
+    /// per byte of the key, so it is slower than `Forward`, but still reads lazily
+    Backward,
+}
+
+/// Key-value pairs in key order (or in reverse order when iterating backward)
+pub type Entries<'txn, E> = Box<dyn Iterator<Item=Result<(Vec<u8>, Vec<u8>), E>> + 'txn>;
+
 pub trait CanRead {
     type ExistsErr;
     fn exists(&self, storage: Storage, path: &[u8]) -> Result<bool, Self::ExistsErr>;
 
     type GetErr;
     fn get(&self, storage: Storage, path: &[u8]) -> Result<Vec<u8>, GetError<Self::GetErr>>;
+
+    type IterErr;
+    /// Iterates over keys in the range. See `Direction::Backward` for the cost of iterating backward
+    fn range(&self, storage: Storage, start: Bound<&[u8]>, end: Bound<&[u8]>, direction: Direction)
+        -> Result<Entries<'_, Self::IterErr>, Self::IterErr>;
+
+    /// Iterates over all keys starting with `prefix`.
+    /// Every path ends with a separator, so prefix of some path covers exactly this path and its descendants.
+    fn prefix(&self, storage: Storage, prefix: &[u8], direction: Direction)
+        -> Result<Entries<'_, Self::IterErr>, Self::IterErr>
+    {
+        let end = prefix_end(prefix);
+        let end = end.as_deref().map_or(Bound::Unbounded, Bound::Excluded);
+        self.range(storage, Bound::Included(prefix), end, direction)
+    }
+}
+
+/// Smallest key that is greater than any key starting with `prefix`.
+/// There is no such key when prefix is empty or consists of `0xFF` only.
+#[must_use]
+pub fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last != u8::MAX {
+            end.push(last + 1);
+            return Some(end)
+        }
+    }
+    None
 }
 
 #[derive(Debug)]
 pub enum SetError<T> {
     NoParentExists,
     SerializationError(Box<dyn std::error::Error>),
+    /// Stored value is saved by a newer version of the program, so it can't be overwritten
+    VersionTooBig {
+        version: usize,
+        max: usize,
+    },
     Other(T)
 }
+/// Value was read successfully, but writing it back failed (or vice versa)
+#[derive(Debug)]
+pub enum UpgradeError<G, S> {
+    Get(GetError<G>),
+    Set(SetError<S>),
+}
+
 #[derive(Debug)]
 pub enum RemoveError<T> {
     NoSuchPath,
+    /// Children record of the parent is broken
+    SerializationError(Box<dyn std::error::Error>),
     Other(T)
 }
 pub trait CanWrite: CanRead {
@@ -43,6 +111,17 @@ pub trait CanWrite: CanRead {
     fn set(&mut self, storage: Storage, path: &[u8], data: &[u8]) -> Result<(), SetError<Self::SetErr>>;
     type RemoveErr;
     fn remove(&mut self, storage: Storage, path: &[u8]) -> Result<(), RemoveError<Self::RemoveErr>>;
+
+    /// How many children are remembered by nodes created in this transaction
+    fn children_size(&self) -> Size {
+        Size::All
+    }
+
+    type CommitErr;
+    /// Makes all changes visible to transactions created after this call
+    fn commit(self) -> Result<(), Self::CommitErr>;
+    /// Discards all changes made in this transaction
+    fn abort(self);
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -64,6 +143,13 @@ impl<'db, D: Database<'db>> CanRead for Ro<'db, D> {
     fn get(&self, storage: Storage, path: &[u8]) -> Result<Vec<u8>, GetError<Self::GetErr>> {
         self.0.get(storage, path)
     }
+
+    type IterErr = <<D as Database<'db>>::RoTxn as CanRead>::IterErr;
+    fn range(&self, storage: Storage, start: Bound<&[u8]>, end: Bound<&[u8]>, direction: Direction)
+        -> Result<Entries<'_, Self::IterErr>, Self::IterErr>
+    {
+        self.0.range(storage, start, end, direction)
+    }
 }
 
 #[derive(Debug)]
@@ -78,6 +164,13 @@ impl<'db, D: Database<'db>> CanRead for Rw<'db, D> {
     fn get(&self, storage: Storage, path: &[u8]) -> Result<Vec<u8>, GetError<Self::GetErr>> {
         self.0.get(storage, path)
     }
+
+    type IterErr = <<D as Database<'db>>::RwTxn as CanRead>::IterErr;
+    fn range(&self, storage: Storage, start: Bound<&[u8]>, end: Bound<&[u8]>, direction: Direction)
+        -> Result<Entries<'_, Self::IterErr>, Self::IterErr>
+    {
+        self.0.range(storage, start, end, direction)
+    }
 }
 impl<'db, D: Database<'db>> CanWrite for Rw<'db, D> {
     type SetErr = <<D as Database<'db>>::RwTxn as CanWrite>::SetErr;
@@ -89,4 +182,30 @@ impl<'db, D: Database<'db>> CanWrite for Rw<'db, D> {
     fn remove(&mut self, storage: Storage, path: &[u8]) -> Result<(), RemoveError<Self::RemoveErr>> {
         self.0.remove(storage, path)
     }
+
+    fn children_size(&self) -> Size {
+        self.0.children_size()
+    }
+
+    type CommitErr = <<D as Database<'db>>::RwTxn as CanWrite>::CommitErr;
+    fn commit(self) -> Result<(), Self::CommitErr> {
+        self.0.commit()
+    }
+
+    fn abort(self) {
+        self.0.abort()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn prefix_end() {
+        assert_eq!(super::prefix_end(b"ab"), Some(b"ac".to_vec()));
+        assert_eq!(super::prefix_end(b"a\xFF"), Some(b"b".to_vec()));
+        assert_eq!(super::prefix_end(b"\xFF\xFF"), None);
+        assert_eq!(super::prefix_end(b""), None);
+    }
 }
@@ -0,0 +1,360 @@
+//! Tree structure on top of raw storage, shared by every backend.
+//!
+//! Node exists when it is root or when it has a record in `Storage::Children`.
+//! Data may be stored only in existing nodes.
+//! Record of the node is a `ChildrenInfo` with segments of its children.
+//! Root has no record until its first child is created.
+//!
+//! Removing a node never removes its ancestors, even ones created by `Parents::Create`.
+//! They stay listed until removed explicitly: by `remove_empty` once they have no children
+//! (this is what `remove` does for nodes without data) or by `remove_recursive`.
+use super::storage::*;
+use crate::path::{ChildrenInfo, Segment, Path};
+use crate::reservoir::{Reservoir, Size};
+use indexmap::IndexSet;
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::num::NonZeroU8;
+
+/// What to do when parent of the node being written does not exist
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parents {
+    /// Fail with `SetError::NoParentExists`
+    Require,
+    /// Create all missing ancestors, like `mkdir -p` does
+    Create,
+}
+
+/// Path of the parent node. Root has no parent.
+#[must_use]
+pub fn parent(path: &[u8]) -> Option<&[u8]> {
+    // Skip separator of the last segment
+    let (_, rest) = path.split_last()?;
+    let pos = rest.iter().rposition(|&b| b == 0)?;
+    Some(&path[..=pos])
+}
+
+pub fn exists<T: CanRead>(txn: &T, path: &[u8]) -> Result<bool, T::ExistsErr> {
+    if parent(path).is_none() {
+        return Ok(true)
+    }
+    txn.exists(Storage::Children, path)
+}
+
+fn last_segment(path: &[u8], parent: &[u8]) -> Segment {
+    // Without separator
+    let bytes = &path[parent.len()..path.len() - 1];
+    Segment(bytes
+        .iter()
+        .map(|&b| NonZeroU8::new(b).expect("segment can't contain separator"))
+        .collect()
+    )
+}
+
+/// Last segment of the path. `None` for root
+#[must_use]
+pub fn segment(path: &[u8]) -> Option<Segment> {
+    parent(path).map(|parent| last_segment(path, parent))
+}
+
+fn empty(size: Size) -> ChildrenInfo {
+    Reservoir::new(size, IndexSet::new())
+}
+
+fn save(info: &ChildrenInfo) -> Result<Vec<u8>, Box<dyn Error>> {
+    let val = rmpv::ext::to_value(info)?;
+    let mut buf = Vec::new();
+    rmpv::encode::write_value(&mut buf, &val)?;
+    Ok(buf)
+}
+
+fn load(data: Vec<u8>) -> Result<ChildrenInfo, Box<dyn Error>> {
+    let mut cur = std::io::Cursor::new(data);
+    let val = rmpv::decode::read_value(&mut cur)?;
+    let res = rmpv::ext::from_value(val)?;
+    Ok(res)
+}
+
+/// Children of some node decoded as `C`
+#[derive(Debug)]
+pub struct Children<C: Path> {
+    /// Children from the sample that were decoded successfully
+    pub items: Vec<C>,
+    /// Children from the sample that are not `C`. Usually they are just of other type
+    pub skipped: Vec<(Segment, C::Error)>,
+    /// Number of all children of the node, including ones that are not in the sample
+    pub total_count: usize,
+}
+
+impl<C: Path> Children<C> {
+    #[must_use]
+    pub fn decode(info: &ChildrenInfo) -> Self {
+        let mut items = Vec::new();
+        let mut skipped = Vec::new();
+        for seg in info.inner() {
+            match C::from_segment(seg.clone()) {
+                Ok(child) => items.push(child),
+                Err(e) => skipped.push((seg.clone(), e)),
+            }
+        }
+        Self {
+            items,
+            skipped,
+            total_count: info.total_count(),
+        }
+    }
+}
+
+impl<C: Path> Default for Children<C> {
+    fn default() -> Self {
+        Self {
+            items: Vec::new(),
+            skipped: Vec::new(),
+            total_count: 0,
+        }
+    }
+}
+
+/// Children record of the node. `None` when node does not exist or it is root without children.
+pub fn children<T: CanRead>(txn: &T, path: &[u8]) -> Result<Option<ChildrenInfo>, GetError<T::GetErr>> {
+    match txn.get(Storage::Children, path) {
+        Ok(data) => load(data).map(Some).map_err(GetError::DeserializationError),
+        Err(GetError::NoSuchPath) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn write_children<T: CanWrite>(txn: &mut T, path: &[u8], info: &ChildrenInfo) -> Result<(), SetError<T::SetErr>> {
+    let data = save(info).map_err(SetError::SerializationError)?;
+    txn.set(Storage::Children, path, &data)
+}
+
+/// Makes sure that node exists and registers it in the parent's record
+pub fn create<T>(txn: &mut T, path: &[u8], parents: Parents) -> Result<(), SetError<T::SetErr>> where
+    T: CanWrite,
+    T::ExistsErr: Into<T::SetErr>,
+    T::GetErr: Into<T::SetErr>,
+{
+    let exists = |txn: &T, path: &[u8]| exists(txn, path).map_err(|e| SetError::Other(e.into()));
+    if exists(txn, path)? {
+        return Ok(())
+    }
+
+    let parent = parent(path).expect("root always exists");
+    if !exists(txn, parent)? {
+        match parents {
+            Parents::Require => return Err(SetError::NoParentExists),
+            Parents::Create => create(txn, parent, parents)?,
+        }
+    }
+    write_children(txn, path, &empty(txn.children_size()))?;
+
+    let siblings = children(txn, parent).map_err(|e| match e {
+        GetError::NoSuchPath => SetError::NoParentExists,
+        GetError::DeserializationError(e) => SetError::SerializationError(e),
+        GetError::Other(e) => SetError::Other(e.into()),
+    })?;
+    let mut siblings = siblings.unwrap_or_else(|| empty(txn.children_size()));
+    siblings.insert(last_segment(path, parent));
+    write_children(txn, parent, &siblings)
+}
+
+fn get_err<E: Into<R>, R>(e: GetError<E>) -> RemoveError<R> {
+    match e {
+        GetError::NoSuchPath => RemoveError::NoSuchPath,
+        GetError::DeserializationError(e) => RemoveError::SerializationError(e),
+        GetError::Other(e) => RemoveError::Other(e.into()),
+    }
+}
+
+fn set_err<E: Into<R>, R>(e: SetError<E>) -> RemoveError<R> {
+    match e {
+        // Backends never check parents or versions themselves
+        SetError::NoParentExists | SetError::VersionTooBig { .. } => unreachable!(),
+        SetError::SerializationError(e) => RemoveError::SerializationError(e),
+        SetError::Other(e) => RemoveError::Other(e.into()),
+    }
+}
+
+/// Removes node from the parent's record
+fn unregister<T>(txn: &mut T, path: &[u8], parent: &[u8]) -> Result<(), RemoveError<T::RemoveErr>> where
+    T: CanWrite,
+    T::GetErr: Into<T::RemoveErr>,
+    T::SetErr: Into<T::RemoveErr>,
+{
+    if let Some(mut siblings) = children(txn, parent).map_err(get_err)? {
+        siblings.remove(&last_segment(path, parent));
+        write_children(txn, parent, &siblings).map_err(set_err)?;
+    }
+    Ok(())
+}
+
+/// Removes node without children and unregisters it from the parent's record.
+/// Data of the node must be removed before. Returns whether node was removed.
+/// Parent is kept even if it has no children left.
+pub fn remove_empty<T>(txn: &mut T, path: &[u8]) -> Result<bool, RemoveError<T::RemoveErr>> where
+    T: CanWrite,
+    T::GetErr: Into<T::RemoveErr>,
+    T::SetErr: Into<T::RemoveErr>,
+{
+    let parent = match parent(path) {
+        Some(parent) => parent,
+        // Root is never removed
+        None => return Ok(false),
+    };
+    match children(txn, path).map_err(get_err)? {
+        Some(info) if info.total_count() == 0 => {},
+        _ => return Ok(false),
+    }
+    txn.remove(Storage::Children, path)?;
+    unregister(txn, path, parent)?;
+    Ok(true)
+}
+
+fn keys<T: CanRead>(txn: &T, storage: Storage, prefix: &[u8]) -> Result<Vec<Vec<u8>>, T::IterErr> {
+    txn.prefix(storage, prefix, Direction::Forward)?
+        .map(|entry| entry.map(|(key, _)| key))
+        .collect()
+}
+
+/// Removes node with all its descendants from both storages and unregisters it from the parent's record.
+/// Returns number of removed nodes.
+pub fn remove_recursive<T>(txn: &mut T, path: &[u8]) -> Result<usize, RemoveError<T::RemoveErr>> where
+    T: CanWrite,
+    T::GetErr: Into<T::RemoveErr>,
+    T::SetErr: Into<T::RemoveErr>,
+    T::IterErr: Into<T::RemoveErr>,
+{
+    let data = keys(txn, Storage::Data, path).map_err(|e| RemoveError::Other(e.into()))?;
+    let nodes = keys(txn, Storage::Children, path).map_err(|e| RemoveError::Other(e.into()))?;
+    let parent = parent(path);
+    if data.is_empty() && nodes.is_empty() && parent.is_some() {
+        return Err(RemoveError::NoSuchPath)
+    }
+
+    for key in &data {
+        txn.remove(Storage::Data, key)?;
+    }
+    for key in &nodes {
+        txn.remove(Storage::Children, key)?;
+    }
+    if let Some(parent) = parent {
+        unregister(txn, path, parent)?;
+    }
+
+    // Every node with data has a record, but let's not rely on it
+    let removed: BTreeSet<_> = data.iter().chain(nodes.iter()).collect();
+    Ok(removed.len())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::memory::MemoryDb;
+
+    #[test]
+    fn parents() {
+        assert_eq!(parent(b"\0Foo\0Bar\0"), Some(&b"\0Foo\0"[..]));
+        assert_eq!(parent(b"\0Foo\0"), Some(&b"\0"[..]));
+        assert_eq!(parent(b"\0"), None);
+    }
+
+    #[test]
+    fn create_parents() {
+        let db = MemoryDb::new();
+        let mut txn = db.rw().unwrap();
+        assert!(exists(&txn, b"\0").unwrap());
+
+        let res = create(&mut txn, b"\0Foo\0Bar\0", Parents::Require);
+        assert!(matches!(res, Err(SetError::NoParentExists)));
+        assert!(!exists(&txn, b"\0Foo\0").unwrap());
+
+        create(&mut txn, b"\0Foo\0Bar\0", Parents::Create).unwrap();
+        assert!(exists(&txn, b"\0Foo\0").unwrap());
+        assert!(exists(&txn, b"\0Foo\0Bar\0").unwrap());
+
+        // Parent exists now
+        create(&mut txn, b"\0Foo\0Baz\0", Parents::Require).unwrap();
+    }
+
+    fn segments(info: &ChildrenInfo) -> Vec<Vec<u8>> {
+        let mut res: Vec<Vec<u8>> = info.inner()
+            .iter()
+            .map(|seg| seg.0.iter().map(|b| b.get()).collect())
+            .collect();
+        res.sort();
+        res
+    }
+
+    #[test]
+    fn maintain_children() {
+        let db = MemoryDb::new();
+        let mut txn = db.rw().unwrap();
+        create(&mut txn, b"\0Foo\0Bar\0", Parents::Create).unwrap();
+        create(&mut txn, b"\0Foo\0Baz\0", Parents::Require).unwrap();
+
+        let root = children(&txn, b"\0").unwrap().unwrap();
+        assert_eq!(segments(&root), vec![b"Foo".to_vec()]);
+        let foo = children(&txn, b"\0Foo\0").unwrap().unwrap();
+        assert_eq!(segments(&foo), vec![b"Bar".to_vec(), b"Baz".to_vec()]);
+        assert_eq!(foo.total_count(), 2);
+
+        // Foo has children
+        assert!(!remove_empty(&mut txn, b"\0Foo\0").unwrap());
+        assert!(remove_empty(&mut txn, b"\0Foo\0Bar\0").unwrap());
+        assert!(!exists(&txn, b"\0Foo\0Bar\0").unwrap());
+        let foo = children(&txn, b"\0Foo\0").unwrap().unwrap();
+        assert_eq!(segments(&foo), vec![b"Baz".to_vec()]);
+    }
+
+    #[test]
+    fn prune_created_parents() {
+        let db = MemoryDb::new();
+        let mut txn = db.rw().unwrap();
+        create(&mut txn, b"\0Foo\0Bar\0", Parents::Create).unwrap();
+        assert!(remove_empty(&mut txn, b"\0Foo\0Bar\0").unwrap());
+
+        // Foo is still listed in the root, though it has neither data nor children
+        assert!(exists(&txn, b"\0Foo\0").unwrap());
+        let foo = children(&txn, b"\0Foo\0").unwrap().unwrap();
+        assert_eq!(foo.total_count(), 0);
+        let root = children(&txn, b"\0").unwrap().unwrap();
+        assert_eq!(segments(&root), vec![b"Foo".to_vec()]);
+
+        assert!(remove_empty(&mut txn, b"\0Foo\0").unwrap());
+        assert!(!exists(&txn, b"\0Foo\0").unwrap());
+        let root = children(&txn, b"\0").unwrap().unwrap();
+        assert_eq!(root.total_count(), 0);
+    }
+
+    #[test]
+    fn remove_subtree() {
+        let db = MemoryDb::new();
+        let mut txn = db.rw().unwrap();
+        create(&mut txn, b"\0Foo\0Bar\0", Parents::Create).unwrap();
+        create(&mut txn, b"\0Foo\0Baz\0", Parents::Require).unwrap();
+        create(&mut txn, b"\0Spam\0", Parents::Require).unwrap();
+        txn.set(Storage::Data, b"\0Foo\0Bar\0", b"data").unwrap();
+
+        assert_eq!(remove_recursive(&mut txn, b"\0Foo\0").unwrap(), 3);
+        assert!(!exists(&txn, b"\0Foo\0").unwrap());
+        assert!(!exists(&txn, b"\0Foo\0Bar\0").unwrap());
+        assert!(!txn.exists(Storage::Data, b"\0Foo\0Bar\0").unwrap());
+        let root = children(&txn, b"\0").unwrap().unwrap();
+        assert_eq!(segments(&root), vec![b"Spam".to_vec()]);
+
+        assert!(matches!(remove_recursive(&mut txn, b"\0Foo\0"), Err(RemoveError::NoSuchPath)));
+    }
+
+    #[test]
+    fn limited_children() {
+        let db = MemoryDb::with_children_size(Size::Maximum(1)).unwrap();
+        let mut txn = db.rw().unwrap();
+        create(&mut txn, b"\0Foo\0", Parents::Require).unwrap();
+        create(&mut txn, b"\0Bar\0", Parents::Require).unwrap();
+
+        let root = children(&txn, b"\0").unwrap().unwrap();
+        assert_eq!(root.inner().len(), 1);
+        assert_eq!(root.total_count(), 2);
+    }
+}
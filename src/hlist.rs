@@ -99,6 +99,27 @@ impl<T: UnwrapAll, L: UnwrapAll> UnwrapAll for Cons<T, L> {
     }
 }
 
+/// Checks that every `Result` in (possibly nested) list is `Ok`
+pub trait AllOk: HList {
+    fn all_ok(&self) -> bool;
+}
+
+impl AllOk for Nil {
+    fn all_ok(&self) -> bool { true }
+}
+
+impl<T, E, L: AllOk> AllOk for Cons<Result<T, E>, L> {
+    fn all_ok(&self) -> bool {
+        self.0.is_ok() && self.1.all_ok()
+    }
+}
+
+impl<T: AllOk, L: AllOk> AllOk for Cons<T, L> {
+    fn all_ok(&self) -> bool {
+        self.0.all_ok() && self.1.all_ok()
+    }
+}
+
 // I like the idea, but this code does not compiling:
 //     error[E0275]: overflow evaluating the requirement `hlist::Nil: hlist::CreateHList<V, {N-1}>`
 // Increasing #![recursion_limit] does not help, so I just left it commented.
@@ -265,6 +286,14 @@ mod test {
         assert_eq!(unwrapped, (1, 'a'));
     }
 
+    #[test]
+    fn all_ok() {
+        let good: HList![Result<i32, ()>, HList![Result<char, ()>]] = hlist![Ok(1), hlist![Ok('a')]];
+        assert!(good.all_ok());
+        let bad: HList![Result<i32, ()>, HList![Result<char, ()>]] = hlist![Ok(1), hlist![Err(())]];
+        assert!(!bad.all_ok());
+    }
+
     #[test]
     fn length() {
         let list = Cons('a', Cons('b', Cons('c', Nil)));
@@ -11,6 +11,7 @@
     const_raw_ptr_deref,
     generic_associated_types,
     trivial_bounds,
+    associated_type_defaults,
 )]
 #![warn(
     missing_docs,
@@ -33,6 +34,8 @@
     clippy::missing_errors_doc
 )]
 #[macro_use] mod fntools;
+// Lets code generated by ttdb-derive refer to `::ttdb` inside this crate too
+extern crate self as ttdb;
 
 #[macro_use] pub mod hlist;
 #[macro_use] pub mod versions;
@@ -1,5 +1,8 @@
 use std::num::NonZeroU8;
+use std::convert::TryInto;
 use std::hash::Hash;
+use std::fmt::{self, Write};
+use std::str::FromStr;
 use crate::reservoir::Reservoir;
 use serde::{Serialize, Deserialize};
 
@@ -49,10 +52,72 @@ impl PathBuf {
 
         res
     }
+
+    /// Splits bytes made by `into_bytes` back into segments
+    /// # Errors
+    /// When bytes don't end with the separator
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, UnterminatedSegment> {
+        let rest = match bytes.split_last() {
+            None => return Ok(Self(Vec::new())),
+            Some((0, rest)) => rest,
+            Some(_) => return Err(UnterminatedSegment),
+        };
+        let segments = rest
+            .split(|&b| b == 0)
+            .map(|seg| Segment(seg.iter().map(|&b| nonzero(b)).collect()))
+            .collect();
+        Ok(Self(segments))
+    }
+}
+
+/// Last segment has no separator after it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UnterminatedSegment;
+
+// Root is an empty segment, so `/Foo` is `["", "Foo"]`. Root alone is `/`
+fn write_segments(f: &mut fmt::Formatter<'_>, texts: &[String]) -> fmt::Result {
+    match texts {
+        [root] if root.is_empty() => f.write_str("/"),
+        _ => f.write_str(&texts.join("/")),
+    }
+}
+
+fn split_segments(text: &str) -> Vec<&str> {
+    match text {
+        "" => Vec::new(),
+        "/" => vec![""],
+        _ => text.split('/').collect(),
+    }
+}
+
+/// Shows path like `/Users/User=%x000000000000002a`. Types of the values are unknown,
+/// so each of them is shown as raw bytes in hex after `%x`. Typed chains show them properly: `/Users/User=42`.
+impl fmt::Display for PathBuf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let texts: Vec<_> = self.0.iter().map(ToString::to_string).collect();
+        write_segments(f, &texts)
+    }
+}
+
+/// Parses only the raw form written by `Display`. Values without `%x`, like in `/Users/User=42`,
+/// are rejected: such text is written by typed chains and must be parsed by them.
+impl FromStr for PathBuf {
+    type Err = ParseError;
+
+    fn from_str(text: &str) -> Result<Self, ParseError> {
+        let segments = split_segments(text)
+            .into_iter()
+            .enumerate()
+            .map(|(index, seg)| seg.parse().map_err(|error| ParseError::Segment { index, error }))
+            .collect::<Result<_, _>>()?;
+        Ok(Self(segments))
+    }
 }
 
 pub trait Path: Sized {
     type AssociatedData: FirstVersion;
+    /// Human-readable name of the path. `path!` uses the identifier, otherwise it is `std::any::type_name`
+    const NAME: &'static str = std::any::type_name::<Self>();
 
     fn into_segment(self) -> Segment;
 
@@ -60,6 +125,18 @@ pub trait Path: Sized {
     /// # Errors
     /// When it is impossible to deserialize from given segment
     fn from_segment(seg: Segment) -> Result<Self, Self::Error>;
+
+    /// Segment as it is shown in text paths, like `User=42`.
+    /// By default it is the text of `into_segment`, so data is shown as bytes.
+    /// Paths that are not `Clone` are shown by their `NAME` unless they override this.
+    fn to_text(&self) -> String {
+        self.segment_text()
+    }
+    /// # Errors
+    /// When text is not written by `to_text` of this path
+    fn from_text(text: &str) -> Result<Self, SegmentError> {
+        Self::from_segment(text.parse()?).map_err(ToSegmentError::segment_error)
+    }
 }
 
 pub trait ParentOf<Child: Path + ?Sized>: Path {}
@@ -72,6 +149,31 @@ mod collect {
     pub trait WeakChain {
         type Last: Path;
         fn collect(self, res: PathBuf) -> PathBuf;
+        /// Decodes rest of the chain. `index` is the position of the first remaining segment
+        fn parse_from(segments: &mut std::vec::IntoIter<Segment>, index: usize) -> Result<Self, ParseError>
+            where Self: Sized;
+        fn texts(&self, res: &mut Vec<String>);
+        /// Same as `parse_from`, but for segments of text path
+        fn parse_text_from(segments: &mut std::vec::IntoIter<&str>, index: usize) -> Result<Self, ParseError>
+            where Self: Sized;
+    }
+
+    fn parse_segment<P: Path>(segments: &mut std::vec::IntoIter<Segment>, index: usize) -> Result<P, ParseError> {
+        let seg = segments.next().expect("length is checked by Chain::parse");
+        P::from_segment(seg).map_err(|e| ParseError::Segment { index, error: e.segment_error() })
+    }
+
+    fn parse_segment_text<P: Path>(segments: &mut std::vec::IntoIter<&str>, index: usize) -> Result<P, ParseError> {
+        let text = segments.next().expect("length is checked by Chain::from_str");
+        P::from_text(text).map_err(|error| ParseError::Segment { index, error })
+    }
+
+    const fn check_length(expected: usize, found: usize) -> Result<(), ParseError> {
+        if expected == found {
+            Ok(())
+        } else {
+            Err(ParseError::Length { expected, found })
+        }
     }
 
     impl<P> WeakChain for Cons<P, Nil> where P: Path
@@ -81,6 +183,18 @@ mod collect {
             res.0.push(self.0.into_segment());
             res
         }
+
+        default fn parse_from(segments: &mut std::vec::IntoIter<Segment>, index: usize) -> Result<Self, ParseError> {
+            Ok(Cons(parse_segment(segments, index)?, Nil))
+        }
+
+        default fn texts(&self, res: &mut Vec<String>) {
+            res.push(self.0.to_text());
+        }
+
+        default fn parse_text_from(segments: &mut std::vec::IntoIter<&str>, index: usize) -> Result<Self, ParseError> {
+            Ok(Cons(parse_segment_text(segments, index)?, Nil))
+        }
     }
 
     impl<P, C, R> WeakChain for Cons<P, Cons<C, R>> where
@@ -93,6 +207,21 @@ mod collect {
             res.0.push(self.0.into_segment());
             self.1.collect(res)
         }
+
+        fn parse_from(segments: &mut std::vec::IntoIter<Segment>, index: usize) -> Result<Self, ParseError> {
+            let head = parse_segment(segments, index)?;
+            Ok(Cons(head, Cons::<C, R>::parse_from(segments, index + 1)?))
+        }
+
+        fn texts(&self, res: &mut Vec<String>) {
+            res.push(self.0.to_text());
+            self.1.texts(res);
+        }
+
+        fn parse_text_from(segments: &mut std::vec::IntoIter<&str>, index: usize) -> Result<Self, ParseError> {
+            let head = parse_segment_text(segments, index)?;
+            Ok(Cons(head, Cons::<C, R>::parse_text_from(segments, index + 1)?))
+        }
     }
 
     #[allow(clippy::doc_markdown)]
@@ -102,11 +231,38 @@ mod collect {
         fn collect(self) -> PathBuf where Self: Sized {
             WeakChain::collect(self, PathBuf(Vec::new()))
         }
+
+        /// Rebuilds the chain from segments, checking each of them with `Path::from_segment`
+        /// # Errors
+        /// When number of segments differs or some segment is not the expected path
+        fn parse(path: PathBuf) -> Result<Self, ParseError> where Self: Sized + Length {
+            check_length(Self::LENGTH, path.0.len())?;
+            Self::parse_from(&mut path.0.into_iter(), 0)
+        }
     }
 
     impl<T> Chain for Cons<Root, T> where Cons<Root, T>: WeakChain {
         type Last = <Self as WeakChain>::Last;
     }
+
+    /// Shows chain like `/Users/User=42`, with values of the data paths
+    impl<T> fmt::Display for Cons<Root, T> where Self: Chain {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            let mut texts = Vec::new();
+            self.texts(&mut texts);
+            write_segments(f, &texts)
+        }
+    }
+
+    impl<T> FromStr for Cons<Root, T> where Self: Chain + Length {
+        type Err = ParseError;
+
+        fn from_str(text: &str) -> Result<Self, ParseError> {
+            let segments = split_segments(text);
+            check_length(Self::LENGTH, segments.len())?;
+            Self::parse_text_from(&mut segments.into_iter(), 0)
+        }
+    }
 }
 
 pub use collect::Chain;
@@ -115,12 +271,453 @@ use crate::versions::FirstVersion;
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct UnexpectedTag;
 
+/// Error of `Chain::parse`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    /// Path has other number of segments than the chain
+    Length {
+        expected: usize,
+        found: usize,
+    },
+    /// Segment number `index` can't be decoded. Root is segment 0
+    Segment {
+        index: usize,
+        error: SegmentError,
+    },
+}
+
+// `Path::Error` is any type, but errors of all paths declared in this crate become `SegmentError`
+trait ToSegmentError {
+    fn segment_error(self) -> SegmentError;
+}
+
+impl<T> ToSegmentError for T {
+    default fn segment_error(self) -> SegmentError {
+        SegmentError::InvalidData
+    }
+}
+
+impl ToSegmentError for UnexpectedTag {
+    fn segment_error(self) -> SegmentError {
+        SegmentError::UnexpectedTag
+    }
+}
+
+impl ToSegmentError for SegmentError {
+    fn segment_error(self) -> SegmentError {
+        self
+    }
+}
+
+// Default `Path::to_text` needs a copy of the path to call `into_segment`
+trait SegmentText {
+    fn segment_text(&self) -> String;
+}
+
+impl<T: Path> SegmentText for T {
+    default fn segment_text(&self) -> String {
+        let mut res = String::new();
+        escape_text(T::NAME.as_bytes(), &mut res);
+        res
+    }
+}
+
+impl<T: Path + Clone> SegmentText for T {
+    fn segment_text(&self) -> String {
+        self.clone().into_segment().to_string()
+    }
+}
+
+/// Error of decoding path with data
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SegmentError {
+    UnexpectedTag,
+    /// Tag is right, but data can't be decoded
+    InvalidData,
+}
+
+impl From<UnexpectedTag> for SegmentError {
+    fn from(_: UnexpectedTag) -> Self {
+        Self::UnexpectedTag
+    }
+}
+
+/// Value stored in the segment next to the tag: `struct User(u64)` or `struct Doc { id: String }`.
+///
+/// Segment with data is the tag, then `=`, then values one by one. Each value is turned into bytes:
+/// - unsigned integers are big-endian
+/// - signed integers are big-endian with the sign bit flipped, so negative numbers go first
+/// - `String` is UTF-8, `bool` is 0 or 1, `Vec<u8>` and `[u8; N]` are stored as is
+///
+/// Then 0x00 is replaced with 0x01 0x02 and 0x01 with 0x01 0x03, and 0x01 0x01 is appended.
+/// Segment never contains zero bytes, values can be split without knowing their types
+/// and bytes of encoded values are ordered exactly as values themselves are.
+/// So iterating over children returns them in the numeric order.
+///
+/// In text paths values are separated by `,`: `Doc=foo,-1`. Numbers and `bool` are written as usual,
+/// bytes other than printable ASCII, `%`, `/`, `,` and `=` are written as `%XX`.
+/// `PathBuf` doesn't know the types, so it writes raw bytes instead: `Doc=%x666f6f,%x7fffffff`.
+pub trait SegmentData: Sized {
+    /// Appends value to the segment
+    fn encode(&self, out: &mut Vec<NonZeroU8>);
+    /// Reads value from the beginning of `data` and returns the rest
+    fn decode(data: &[NonZeroU8]) -> Result<(Self, &[NonZeroU8]), SegmentError>;
+    /// Value in text paths before escaping
+    fn to_text(&self) -> Vec<u8>;
+    fn from_text(text: &[u8]) -> Result<Self, SegmentError>;
+}
+
+const ESCAPE: u8 = 0x01;
+/// Separates tag from data
+const DATA: u8 = b'=';
+
+fn nonzero(byte: u8) -> NonZeroU8 {
+    NonZeroU8::new(byte).expect("zero byte in segment")
+}
+
+/// Appends escaped bytes followed by the end marker.
+/// End marker is less than anything else, so shorter values go first
+pub fn encode_bytes(bytes: &[u8], out: &mut Vec<NonZeroU8>) {
+    for &b in bytes {
+        match b {
+            0x00 | ESCAPE => out.extend_from_slice(&[nonzero(ESCAPE), nonzero(b + 2)]),
+            b => out.push(nonzero(b)),
+        }
+    }
+    out.extend_from_slice(&[nonzero(ESCAPE), nonzero(0x01)]);
+}
+
+/// Reads bytes written by `encode_bytes` and returns the rest
+pub fn decode_bytes(data: &[NonZeroU8]) -> Result<(Vec<u8>, &[NonZeroU8]), SegmentError> {
+    let mut res = Vec::new();
+    let mut iter = data.iter().enumerate();
+    while let Some((i, b)) = iter.next() {
+        if b.get() != ESCAPE {
+            res.push(b.get());
+            continue
+        }
+        match iter.next().map(|(_, b)| b.get()) {
+            Some(0x01) => return Ok((res, &data[i + 2..])),
+            Some(escaped @ 0x02..=0x03) => res.push(escaped - 2),
+            _ => return Err(SegmentError::InvalidData),
+        }
+    }
+    // No end marker
+    Err(SegmentError::InvalidData)
+}
+
+impl SegmentData for Vec<u8> {
+    fn encode(&self, out: &mut Vec<NonZeroU8>) {
+        encode_bytes(self, out);
+    }
+
+    fn decode(data: &[NonZeroU8]) -> Result<(Self, &[NonZeroU8]), SegmentError> {
+        decode_bytes(data)
+    }
+
+    fn to_text(&self) -> Vec<u8> {
+        self.clone()
+    }
+
+    fn from_text(text: &[u8]) -> Result<Self, SegmentError> {
+        Ok(text.to_vec())
+    }
+}
+
+impl<const N: usize> SegmentData for [u8; N] {
+    fn encode(&self, out: &mut Vec<NonZeroU8>) {
+        encode_bytes(self, out);
+    }
+
+    fn decode(data: &[NonZeroU8]) -> Result<(Self, &[NonZeroU8]), SegmentError> {
+        let (bytes, rest) = decode_bytes(data)?;
+        if bytes.len() != N {
+            return Err(SegmentError::InvalidData)
+        }
+        let mut res = [0; N];
+        res.copy_from_slice(&bytes);
+        Ok((res, rest))
+    }
+
+    fn to_text(&self) -> Vec<u8> {
+        self.to_vec()
+    }
+
+    fn from_text(text: &[u8]) -> Result<Self, SegmentError> {
+        text.try_into().map_err(|_| SegmentError::InvalidData)
+    }
+}
+
+impl SegmentData for String {
+    fn encode(&self, out: &mut Vec<NonZeroU8>) {
+        encode_bytes(self.as_bytes(), out);
+    }
+
+    fn decode(data: &[NonZeroU8]) -> Result<(Self, &[NonZeroU8]), SegmentError> {
+        let (bytes, rest) = decode_bytes(data)?;
+        let res = Self::from_utf8(bytes).map_err(|_| SegmentError::InvalidData)?;
+        Ok((res, rest))
+    }
+
+    fn to_text(&self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+
+    fn from_text(text: &[u8]) -> Result<Self, SegmentError> {
+        Self::from_utf8(text.to_vec()).map_err(|_| SegmentError::InvalidData)
+    }
+}
+
+impl SegmentData for bool {
+    fn encode(&self, out: &mut Vec<NonZeroU8>) {
+        encode_bytes(&[(*self).into()], out);
+    }
+
+    fn decode(data: &[NonZeroU8]) -> Result<(Self, &[NonZeroU8]), SegmentError> {
+        match decode_bytes(data)? {
+            (bytes, rest) if bytes == [0] => Ok((false, rest)),
+            (bytes, rest) if bytes == [1] => Ok((true, rest)),
+            _ => Err(SegmentError::InvalidData),
+        }
+    }
+
+    fn to_text(&self) -> Vec<u8> {
+        self.to_string().into_bytes()
+    }
+
+    fn from_text(text: &[u8]) -> Result<Self, SegmentError> {
+        parse_text(text)
+    }
+}
+
+fn parse_text<T: FromStr>(text: &[u8]) -> Result<T, SegmentError> {
+    std::str::from_utf8(text)
+        .ok()
+        .and_then(|text| text.parse().ok())
+        .ok_or(SegmentError::InvalidData)
+}
+
+macro_rules! impl_segment_data_for_number {
+    ($($num:ty => $unsigned:ty),* $(,)?) => {
+        $(
+            impl SegmentData for $num {
+                #[allow(clippy::cast_sign_loss, trivial_numeric_casts)]
+                fn encode(&self, out: &mut Vec<NonZeroU8>) {
+                    // Zero for unsigned numbers
+                    const SIGN: $unsigned = <$num>::min_value() as $unsigned;
+                    let flipped = (*self as $unsigned) ^ SIGN;
+                    encode_bytes(&flipped.to_be_bytes(), out);
+                }
+
+                #[allow(clippy::cast_possible_wrap, clippy::cast_sign_loss, trivial_numeric_casts)]
+                fn decode(data: &[NonZeroU8]) -> Result<(Self, &[NonZeroU8]), SegmentError> {
+                    const SIGN: $unsigned = <$num>::min_value() as $unsigned;
+                    let (bytes, rest) = decode_bytes(data)?;
+                    let bytes = bytes[..].try_into().map_err(|_| SegmentError::InvalidData)?;
+                    let flipped = <$unsigned>::from_be_bytes(bytes);
+                    Ok(((flipped ^ SIGN) as $num, rest))
+                }
+
+                fn to_text(&self) -> Vec<u8> {
+                    self.to_string().into_bytes()
+                }
+
+                fn from_text(text: &[u8]) -> Result<Self, SegmentError> {
+                    parse_text(text)
+                }
+            }
+        )*
+    };
+}
+
+impl_segment_data_for_number!(
+    u8 => u8, i8 => u8,
+    u16 => u16, i16 => u16,
+    u32 => u32, i32 => u32,
+    u64 => u64, i64 => u64,
+    u128 => u128, i128 => u128,
+);
+
+/// Tag followed by the data marker. Used by `path!`
+#[doc(hidden)]
+#[must_use]
+pub fn data_start(tag: &[NonZeroU8]) -> Vec<NonZeroU8> {
+    let mut res = tag.to_vec();
+    res.push(nonzero(DATA));
+    res
+}
+
+/// Checks tag and returns data. Used by `path!`
+#[doc(hidden)]
+pub fn strip_tag<'a>(seg: &'a [NonZeroU8], tag: &[NonZeroU8]) -> Result<&'a [NonZeroU8], SegmentError> {
+    if !seg.starts_with(tag) {
+        return Err(SegmentError::UnexpectedTag)
+    }
+    match &seg[tag.len()..] {
+        [marker, data @ ..] if marker.get() == DATA => Ok(data),
+        _ => Err(SegmentError::UnexpectedTag),
+    }
+}
+
+fn plain(seg: &[NonZeroU8]) -> Vec<u8> {
+    seg.iter().map(|b| b.get()).collect()
+}
+
+fn escape_text(bytes: &[u8], out: &mut String) {
+    for &b in bytes {
+        if b.is_ascii_graphic() && !b"%/,=".contains(&b) {
+            out.push(char::from(b));
+        } else {
+            write!(out, "%{:02X}", b).expect("writing into string can't fail");
+        }
+    }
+}
+
+// Anything except `%XX` is taken as is, so non-ASCII text can be typed without escaping
+fn unescape_text(text: &str) -> Result<Vec<u8>, SegmentError> {
+    let bytes = text.as_bytes();
+    let mut res = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'%' {
+            res.push(bytes[i]);
+            i += 1;
+            continue
+        }
+        let hex = bytes.get(i + 1..i + 3).ok_or(SegmentError::InvalidData)?;
+        if !hex.iter().all(u8::is_ascii_hexdigit) {
+            return Err(SegmentError::InvalidData)
+        }
+        let hex = std::str::from_utf8(hex).expect("hex digits are ASCII");
+        res.push(u8::from_str_radix(hex, 16).expect("hex digits are checked"));
+        i += 3;
+    }
+    Ok(res)
+}
+
+/// Marks values of untyped segments. Typed text never contains it, since `%` is always followed by hex digits
+const RAW: &str = "%x";
+
+fn raw_text(value: &[u8], out: &mut String) {
+    out.push_str(RAW);
+    for b in value {
+        write!(out, "{:02x}", b).expect("writing into string can't fail");
+    }
+}
+
+fn parse_raw_text(text: &str) -> Result<Vec<u8>, SegmentError> {
+    if !text.starts_with(RAW) {
+        return Err(SegmentError::InvalidData)
+    }
+    let hex = &text.as_bytes()[RAW.len()..];
+    if hex.len() % 2 != 0 || !hex.iter().all(u8::is_ascii_hexdigit) {
+        return Err(SegmentError::InvalidData)
+    }
+    Ok(hex
+        .chunks(2)
+        .map(|pair| {
+            let pair = std::str::from_utf8(pair).expect("hex digits are ASCII");
+            u8::from_str_radix(pair, 16).expect("hex digits are checked")
+        })
+        .collect()
+    )
+}
+
+fn nonzero_bytes(bytes: Vec<u8>) -> Result<Vec<NonZeroU8>, SegmentError> {
+    bytes.into_iter().map(|b| NonZeroU8::new(b).ok_or(SegmentError::InvalidData)).collect()
+}
+
+/// Text of the tag. Used by `path!`
+#[doc(hidden)]
+#[must_use]
+pub fn tag_text(tag: &[NonZeroU8]) -> String {
+    let mut res = String::new();
+    escape_text(&plain(tag), &mut res);
+    res
+}
+
+/// Tag, `=` and values separated by `,`. Used by `path!`
+#[doc(hidden)]
+#[must_use]
+pub fn data_text(tag: &[NonZeroU8], values: &[Vec<u8>]) -> String {
+    let mut res = tag_text(tag);
+    res.push('=');
+    for (i, value) in values.iter().enumerate() {
+        if i > 0 {
+            res.push(',');
+        }
+        escape_text(value, &mut res);
+    }
+    res
+}
+
+/// Checks tag and returns unescaped values. Used by `path!`
+#[doc(hidden)]
+pub fn strip_text_tag(text: &str, tag: &[NonZeroU8]) -> Result<Vec<Vec<u8>>, SegmentError> {
+    let pos = text.find('=').ok_or(SegmentError::UnexpectedTag)?;
+    if unescape_text(&text[..pos])? != plain(tag) {
+        return Err(SegmentError::UnexpectedTag)
+    }
+    text[pos + 1..].split(',').map(unescape_text).collect()
+}
+
+// Tag and values of the segment, if it looks like a segment with data
+fn data_values(seg: &[NonZeroU8]) -> Option<(&[NonZeroU8], Vec<Vec<u8>>)> {
+    let pos = seg.iter().position(|b| b.get() == DATA)?;
+    let mut data = &seg[pos + 1..];
+    let mut values = Vec::new();
+    while !data.is_empty() {
+        let (value, rest) = decode_bytes(data).ok()?;
+        values.push(value);
+        data = rest;
+    }
+    if values.is_empty() {
+        return None
+    }
+    Some((&seg[..pos], values))
+}
+
+impl fmt::Display for Segment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (tag, values) = match data_values(&self.0) {
+            Some(data) => data,
+            None => return f.write_str(&tag_text(&self.0)),
+        };
+        let mut res = tag_text(tag);
+        res.push('=');
+        for (i, value) in values.iter().enumerate() {
+            if i > 0 {
+                res.push(',');
+            }
+            raw_text(value, &mut res);
+        }
+        f.write_str(&res)
+    }
+}
+
+impl FromStr for Segment {
+    type Err = SegmentError;
+
+    fn from_str(text: &str) -> Result<Self, SegmentError> {
+        let pos = match text.find('=') {
+            Some(pos) => pos,
+            None => return Ok(Self(nonzero_bytes(unescape_text(text)?)?)),
+        };
+        let mut res = data_start(&nonzero_bytes(unescape_text(&text[..pos])?)?);
+        for value in text[pos + 1..].split(',') {
+            encode_bytes(&parse_raw_text(value)?, &mut res);
+        }
+        Ok(Self(res))
+    }
+}
+
 /// Important root path.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Root;
 
 impl Path for Root {
     type AssociatedData = !;
+    const NAME: &'static str = "";
 
     fn into_segment(self) -> Segment {
         Segment(Vec::new())
@@ -142,6 +739,7 @@ impl Path for Root {
 pub struct Any(Segment);
 impl Path for Any {
     type AssociatedData = !;
+    const NAME: &'static str = "*";
 
     fn into_segment(self) -> Segment {
         self.0
@@ -158,19 +756,118 @@ impl Path for Any {
 impl<T: Path> ParentOf<T> for Any {}
 impl ParentOf<Any> for Root {}
 
+/// Declares paths and relations between them.
+///
+/// Paths may carry data, which is stored in the segment after the tag (see `SegmentData`).
+/// Chains of such paths are shown and parsed like `/Users/User=42`:
+/// ```
+/// # #![feature(never_type)]
+/// use ttdb::path;
+/// use ttdb::path::{Chain, Root};
+///
+/// path!(
+///     struct Users;
+///     pub struct User(pub u64)[String];
+///     struct Doc { pub id: String }[u8];
+/// );
+/// path!(Root -> {Users -> {User -> {Doc}}});
+///
+/// let chain = ttdb::hlist![Root, Users, User(42), Doc { id: "cv".to_string() }];
+/// assert_eq!(chain.to_string(), "/Users/User=42/Doc=cv");
+/// let parsed: ttdb::HList![Root, Users, User, Doc] = "/Users/User=42/Doc=cv".parse().unwrap();
+/// assert_eq!(parsed.collect(), chain.collect());
+/// ```
+///
+/// Tuple structs may have at most 8 fields, structs with named fields have no such limit.
+/// Paths without data are unit structs, `struct Empty()` and `struct Empty {}` are rejected.
+/// Messages of these errors are checked by `tests/ui`.
 #[macro_export]
 macro_rules! path {
-    ($($vis:vis struct $id:ident $([$assoc:ty])?;)+) => {
+    ($($vis:vis struct $id:ident $(($($tuple:tt)*))? $({$($named:tt)*})? $([$assoc:ty])?;)+) => {
         $(
-            #[derive(Debug, Clone, Copy, Hash, Ord, PartialOrd, PartialEq, Eq)]
-            $vis struct $id;
-            $crate::path!(@impl for $id $(with $assoc)?);
+            $crate::path!(@struct $vis $id $(($($tuple)*))? $({$($named)*})? [$($assoc)?]);
         )+
     };
-    (@impl for $id:ident) => {
-        $crate::path!(@impl for $id with !);
+    (@struct $vis:vis $id:ident [$($assoc:ty)?]) => {
+        #[derive(Debug, Clone, Copy, Hash, Ord, PartialOrd, PartialEq, Eq)]
+        $vis struct $id;
+        $crate::path!(@impl for $id $(with $assoc)?);
     };
-    (@impl for $id:ident with $data:ty) => {
+    (@struct $vis:vis $id:ident () [$($assoc:ty)?]) => {
+        compile_error!(concat!("path without data must be a unit struct: struct ", stringify!($id), ";"));
+    };
+    (@struct $vis:vis $id:ident {} [$($assoc:ty)?]) => {
+        compile_error!(concat!("path without data must be a unit struct: struct ", stringify!($id), ";"));
+    };
+    (@struct $vis:vis $id:ident ($($fvis:vis $fty:ty),* $(,)?) [$($assoc:ty)?]) => {
+        // Tuple fields have no names, so give them some
+        $crate::path!(@tuple $vis $id [$($assoc)?] [] [__f0 __f1 __f2 __f3 __f4 __f5 __f6 __f7] $($fvis $fty,)*);
+    };
+    (@tuple $vis:vis $id:ident [$($assoc:ty)?] [$($done:tt)*] [$name:ident $($names:ident)*] $fvis:vis $fty:ty, $($rest:tt)*) => {
+        $crate::path!(@tuple $vis $id [$($assoc)?] [$($done)* ($name $fvis $fty)] [$($names)*] $($rest)*);
+    };
+    (@tuple $vis:vis $id:ident [$($assoc:ty)?] [$($done:tt)*] [] $fvis:vis $fty:ty, $($rest:tt)*) => {
+        compile_error!(concat!("path! supports at most 8 tuple fields, use named fields in ", stringify!($id)));
+    };
+    (@tuple $vis:vis $id:ident [$($assoc:ty)?] [$(($name:ident $fvis:vis $fty:ty))*] [$($unused:ident)*]) => {
+        #[derive(Debug, Clone, Hash, Ord, PartialOrd, PartialEq, Eq)]
+        $vis struct $id($($fvis $fty),*);
+        $crate::path!(@data $id [$($assoc)?] [$($name: $fty),*] Self($($name),*));
+    };
+    (@struct $vis:vis $id:ident {$($fvis:vis $fname:ident: $fty:ty),* $(,)?} [$($assoc:ty)?]) => {
+        #[derive(Debug, Clone, Hash, Ord, PartialOrd, PartialEq, Eq)]
+        $vis struct $id {
+            $($fvis $fname: $fty),*
+        }
+        $crate::path!(@data $id [$($assoc)?] [$($fname: $fty),*] Self { $($fname),* });
+    };
+    (@data $id:ident [] [$($field:ident: $fty:ty),*] $($ctor:tt)*) => {
+        $crate::path!(@data $id [!] [$($field: $fty),*] $($ctor)*);
+    };
+    (@data $id:ident [$data:ty] [$($field:ident: $fty:ty),*] $($ctor:tt)*) => {
+        $crate::path!(@tag $id);
+
+        impl $crate::path::Path for $id {
+            type AssociatedData = $data;
+            const NAME: &'static str = stringify!($id);
+
+            fn into_segment(self) -> $crate::path::Segment {
+                let $($ctor)* = self;
+                let mut res = $crate::path::data_start(Self::TAG);
+                $( $crate::path::SegmentData::encode(&$field, &mut res); )*
+                $crate::path::Segment(res)
+            }
+
+            type Error = $crate::path::SegmentError;
+
+            fn from_segment(seg: $crate::path::Segment) -> Result<Self, Self::Error> {
+                let data = $crate::path::strip_tag(&seg.0, Self::TAG)?;
+                $( let ($field, data) = <$fty as $crate::path::SegmentData>::decode(data)?; )*
+                if !data.is_empty() {
+                    return Err($crate::path::SegmentError::InvalidData)
+                }
+                Ok($($ctor)*)
+            }
+
+            fn to_text(&self) -> String {
+                let $($ctor)* = self;
+                $crate::path::data_text(Self::TAG, &[$($crate::path::SegmentData::to_text($field)),*])
+            }
+
+            fn from_text(text: &str) -> Result<Self, $crate::path::SegmentError> {
+                let mut values = $crate::path::strip_text_tag(text, Self::TAG)?.into_iter();
+                $(
+                    let value = values.next().ok_or($crate::path::SegmentError::InvalidData)?;
+                    let $field = <$fty as $crate::path::SegmentData>::from_text(&value)?;
+                )*
+                if values.next().is_some() {
+                    return Err($crate::path::SegmentError::InvalidData)
+                }
+                Ok($($ctor)*)
+            }
+        }
+    };
+    (@tag $id:ident) => {
         impl $id {
             #[allow(trivial_casts)]
             const TAG: &'static [::std::num::NonZeroU8] = unsafe {
@@ -184,9 +881,16 @@ macro_rules! path {
                 )
             };
         }
+    };
+    (@impl for $id:ident) => {
+        $crate::path!(@impl for $id with !);
+    };
+    (@impl for $id:ident with $data:ty) => {
+        $crate::path!(@tag $id);
 
         impl $crate::path::Path for $id {
             type AssociatedData = $data;
+            const NAME: &'static str = stringify!($id);
 
             fn into_segment(self) -> $crate::path::Segment {
                 $crate::path::Segment(Self::TAG.to_vec())
@@ -201,6 +905,18 @@ macro_rules! path {
                     Err($crate::path::UnexpectedTag)
                 }
             }
+
+            fn to_text(&self) -> String {
+                $crate::path::tag_text(Self::TAG)
+            }
+
+            fn from_text(text: &str) -> Result<Self, $crate::path::SegmentError> {
+                if text == $crate::path::tag_text(Self::TAG) {
+                    Ok(Self)
+                } else {
+                    Err($crate::path::SegmentError::UnexpectedTag)
+                }
+            }
         }
     };
     ($parent:ident $(
@@ -221,6 +937,7 @@ macro_rules! path {
 mod test {
     use super::*;
     use static_assertions as sa;
+    use crate::hlist::{Cons, Nil};
 
     path! {
         struct Foo[u8];
@@ -260,6 +977,95 @@ mod test {
     // Chain should start with Root
     sa::assert_not_impl_any!(HList![Foo, Bar]: Chain);
 
+    path! {
+        struct Users;
+        struct User(pub u64, String)[String];
+        struct Doc { pub id: String, version: i32 };
+    }
+    path!(Root -> {Users -> {User -> {Doc}}});
+
+    #[test]
+    fn data() {
+        let user = User(42, "a\0b\x01".to_string());
+        let seg = user.clone().into_segment();
+        let bytes: Vec<u8> = seg.0.iter().map(|b| b.get()).collect();
+        let expected = [
+            &b"User="[..],
+            &b"\x01\x02".repeat(7)[..], &b"\x2a\x01\x01"[..],  // 42_u64
+            &b"a\x01\x02b\x01\x03\x01\x01"[..],
+        ].concat();
+        assert_eq!(bytes, expected);
+        assert_eq!(User::from_segment(seg), Ok(user));
+
+        let doc = Doc { id: "foo".to_string(), version: -1 };
+        assert_eq!(Doc::from_segment(doc.clone().into_segment()), Ok(doc));
+
+        // Wrong tag, missing data and extra data
+        assert_eq!(User::from_segment(Users.into_segment()), Err(SegmentError::UnexpectedTag));
+        let mut seg = data_start(User::TAG);
+        42_u64.encode(&mut seg);
+        assert_eq!(User::from_segment(Segment(seg.clone())), Err(SegmentError::InvalidData));
+        "a".to_string().encode(&mut seg);
+        1_u8.encode(&mut seg);
+        assert_eq!(User::from_segment(Segment(seg)), Err(SegmentError::InvalidData));
+    }
+
+    #[test]
+    fn data_chain() {
+        let chain = hlist![Root, Users, User(1, String::new()), Doc { id: "x".to_string(), version: 2 }];
+        let expected = [
+            &b"\0Users\0User="[..],
+            &b"\x01\x02".repeat(7)[..], &b"\x01\x03\x01\x01"[..],  // 1_u64
+            &b"\x01\x01"[..],  // Empty string
+            &b"\0Doc=x\x01\x01"[..],
+            &b"\x80\x01\x02\x01\x02\x02\x01\x01"[..],  // 2_i32
+            &b"\0"[..],
+        ].concat();
+        assert_eq!(chain.collect().into_bytes(), expected);
+    }
+
+    fn encoded<T: SegmentData>(val: &T) -> Vec<u8> {
+        let mut res = Vec::new();
+        val.encode(&mut res);
+        res.into_iter().map(NonZeroU8::get).collect()
+    }
+
+    fn assert_order<T: SegmentData + Ord + Clone + std::fmt::Debug>(mut values: Vec<T>) {
+        values.sort();
+        let mut bytes: Vec<_> = values.iter().map(encoded).collect();
+        bytes.sort();
+        let decoded: Vec<T> = bytes.iter()
+            .map(|b| {
+                let data: Vec<_> = b.iter().map(|&x| NonZeroU8::new(x).unwrap()).collect();
+                let (val, rest) = T::decode(&data).unwrap();
+                assert!(rest.is_empty());
+                val
+            })
+            .collect();
+        assert_eq!(decoded, values);
+    }
+
+    #[test]
+    fn order() {
+        assert_order(vec![0_u64, 1, 2, 255, 256, 1 << 40, u64::max_value()]);
+        assert_order(vec![i32::min_value(), -256, -1, 0, 1, 255, i32::max_value()]);
+        assert_order(vec![i8::min_value(), -1, 0, 1, i8::max_value()]);
+        assert_order(vec![
+            String::new(), "\0".to_string(), "\0\0".to_string(), "\x01".to_string(),
+            "\x02".to_string(), "a".to_string(), "a\0".to_string(), "ab".to_string(),
+        ]);
+        assert_order(vec![vec![], vec![0_u8], vec![0, 1], vec![1], vec![255]]);
+        assert_order(vec![false, true]);
+        assert_order(vec![[0_u8, 0], [0, 1], [1, 0], [255, 255]]);
+
+        // Several fields: first one is compared first
+        let mut a = encoded(&1_u8);
+        a.extend(encoded(&"b".to_string()));
+        let mut b = encoded(&2_u8);
+        b.extend(encoded(&"a".to_string()));
+        assert!(a < b);
+    }
+
     #[test]
     fn tag() {
         let expected = b"Foo"
@@ -276,6 +1082,144 @@ mod test {
         assert_eq!(collected.into_bytes(), b"\0Foo\0Bar\0Baz\0")
     }
 
+    #[test]
+    fn from_bytes() {
+        let path = hlist![Root, Foo, Bar, Baz].collect();
+        assert_eq!(PathBuf::from_bytes(&path.clone().into_bytes()), Ok(path));
+        assert_eq!(PathBuf::from_bytes(b""), Ok(PathBuf(Vec::new())));
+        assert_eq!(PathBuf::from_bytes(b"\0"), Ok(hlist![Root].collect()));
+        assert_eq!(PathBuf::from_bytes(b"\0Foo"), Err(UnterminatedSegment));
+    }
+
+    #[test]
+    fn parse_chain() {
+        let user = User(7, "x".to_string());
+        let bytes = hlist![Root, Users, user.clone()].collect().into_bytes();
+        let path = PathBuf::from_bytes(&bytes).unwrap();
+        let Cons(Root, Cons(Users, Cons(parsed, Nil))) = <HList![Root, Users, User]>::parse(path.clone()).unwrap();
+        assert_eq!(parsed, user);
+
+        let res = <HList![Root, Users, User, Doc]>::parse(path.clone());
+        assert_eq!(res.err(), Some(ParseError::Length { expected: 4, found: 3 }));
+
+        let res = <HList![Root, Foo, Bar]>::parse(path);
+        assert_eq!(res.err(), Some(ParseError::Segment { index: 1, error: SegmentError::UnexpectedTag }));
+
+        let path = PathBuf::from_bytes(b"\0Users\0User=\0").unwrap();
+        let res = <HList![Root, Users, User]>::parse(path);
+        assert_eq!(res.err(), Some(ParseError::Segment { index: 2, error: SegmentError::InvalidData }));
+
+        // Any accepts everything
+        let path = PathBuf::from_bytes(b"\0Spam\0").unwrap();
+        assert!(<HList![Root, Any]>::parse(path).is_ok());
+    }
+
+    #[test]
+    fn text() {
+        let chain = hlist![Root, Users, User(42, "a/b,c".to_string()), Doc { id: "é".to_string(), version: -1 }];
+        let text = "/Users/User=42,a%2Fb%2Cc/Doc=%C3%A9,-1";
+        assert_eq!(chain.to_string(), text);
+        let parsed: HList![Root, Users, User, Doc] = text.parse().unwrap();
+        assert_eq!(parsed.collect(), chain.clone().collect());
+        // Unescaped non-ASCII is fine too
+        let parsed: HList![Root, Users, User, Doc] = "/Users/User=42,a%2Fb%2Cc/Doc=é,-1".parse().unwrap();
+        assert_eq!(parsed.collect(), chain.clone().collect());
+
+        // Untyped path shows values as raw bytes
+        let path = chain.collect();
+        let text = "/Users/User=%x000000000000002a,%x612f622c63/Doc=%xc3a9,%x7fffffff";
+        assert_eq!(path.to_string(), text);
+        assert_eq!(text.parse(), Ok(path));
+
+        assert_eq!(hlist![Root].to_string(), "/");
+        assert!("/".parse::<HList![Root]>().is_ok());
+        assert_eq!("/".parse(), Ok(hlist![Root].collect()));
+        assert_eq!(hlist![Root, Foo, Bar].to_string(), "/Foo/Bar");
+
+        // Segments which don't look like data are escaped entirely
+        let odd = PathBuf(vec![Segment(Vec::new()), Segment(data_start(Foo::TAG))]);
+        assert_eq!(odd.to_string(), "/Foo%3D");
+        assert_eq!("/Foo%3D".parse(), Ok(odd));
+    }
+
+    // Written by hand, so text form is the default one
+    #[derive(Debug, Clone, PartialEq)]
+    struct Manual(u8);
+    impl Path for Manual {
+        type AssociatedData = !;
+        const NAME: &'static str = "Manual";
+
+        fn into_segment(self) -> Segment {
+            let mut res = data_start(&nonzero_bytes(b"Manual".to_vec()).unwrap());
+            self.0.encode(&mut res);
+            Segment(res)
+        }
+
+        type Error = SegmentError;
+
+        fn from_segment(seg: Segment) -> Result<Self, Self::Error> {
+            let data = strip_tag(&seg.0, &nonzero_bytes(b"Manual".to_vec()).unwrap())?;
+            match u8::decode(data)? {
+                (val, []) => Ok(Self(val)),
+                _ => Err(SegmentError::InvalidData),
+            }
+        }
+    }
+    impl ParentOf<Manual> for Root {}
+
+    #[test]
+    fn default_text() {
+        let chain = hlist![Root, Manual(42)];
+        assert_eq!(chain.to_string(), "/Manual=%x2a");
+        assert_eq!("/Manual=%x2a".parse::<HList![Root, Manual]>().map(Chain::collect), Ok(chain.collect()));
+        let res = "/Foo".parse::<HList![Root, Manual]>();
+        assert_eq!(res.err(), Some(ParseError::Segment { index: 1, error: SegmentError::UnexpectedTag }));
+
+        assert_eq!(hlist![Root, Any(Foo.into_segment())].to_string(), "/Foo");
+        assert!("/".parse::<HList![Root]>().is_ok());
+    }
+
+    #[test]
+    fn text_errors() {
+        type UserChain = HList![Root, Users, User];
+        let res = "/Users/User=x,a".parse::<UserChain>();
+        assert_eq!(res.err(), Some(ParseError::Segment { index: 2, error: SegmentError::InvalidData }));
+        let res = "/Users/User=1".parse::<UserChain>();
+        assert_eq!(res.err(), Some(ParseError::Segment { index: 2, error: SegmentError::InvalidData }));
+        let res = "/Users/Doc=1,a".parse::<UserChain>();
+        assert_eq!(res.err(), Some(ParseError::Segment { index: 2, error: SegmentError::UnexpectedTag }));
+        let res = "/Foo/User=1,a".parse::<UserChain>();
+        assert_eq!(res.err(), Some(ParseError::Segment { index: 1, error: SegmentError::UnexpectedTag }));
+        let res = "/Users".parse::<UserChain>();
+        assert_eq!(res.err(), Some(ParseError::Length { expected: 3, found: 2 }));
+
+        let res = "/Foo/%zz".parse::<PathBuf>();
+        assert_eq!(res, Err(ParseError::Segment { index: 2, error: SegmentError::InvalidData }));
+        let res = "/Foo%00".parse::<PathBuf>();
+        assert_eq!(res, Err(ParseError::Segment { index: 1, error: SegmentError::InvalidData }));
+        let res = "/Foo=%x2".parse::<PathBuf>();
+        assert_eq!(res, Err(ParseError::Segment { index: 1, error: SegmentError::InvalidData }));
+    }
+
+    #[test]
+    fn typed_and_raw_text() {
+        type UserChain = HList![Root, Users, User];
+        let chain = hlist![Root, Users, User(42, "a".to_string())];
+        let typed = chain.to_string();
+        let raw = chain.clone().collect().to_string();
+        assert_eq!(typed, "/Users/User=42,a");
+        assert_eq!(raw, "/Users/User=%x000000000000002a,%x61");
+
+        // Each form is parsed only by its own parser, so `42` is never taken for bytes `"42"`
+        let res = typed.parse::<PathBuf>();
+        assert_eq!(res, Err(ParseError::Segment { index: 2, error: SegmentError::InvalidData }));
+        let res = raw.parse::<UserChain>();
+        assert_eq!(res.err(), Some(ParseError::Segment { index: 2, error: SegmentError::InvalidData }));
+
+        assert_eq!(typed.parse::<UserChain>().map(Chain::collect), Ok(chain.clone().collect()));
+        assert_eq!(raw.parse::<PathBuf>(), Ok(chain.collect()));
+    }
+
     #[test]
     fn collect_chain() {
         let chain = hlist![Root, Foo, Bar, Baz];
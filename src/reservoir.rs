@@ -12,6 +12,12 @@ pub enum Size {
     Maximum(usize)
 }
 
+impl Default for Size {
+    fn default() -> Self {
+        Self::All
+    }
+}
+
 #[derive(Derivative, Clone, Serialize, Deserialize)]
 #[derivative(Debug(bound="T: Hash+Eq+std::fmt::Debug"))]
 #[serde(bound(
@@ -40,9 +46,9 @@ impl<T: Hash + Eq> Reservoir<T> {
         match self {
             Self::Unlimited(buf) => {
                 if buf.insert(val) {
-                    InsertionResult::Overwritten
-                } else {
                     InsertionResult::Inserted
+                } else {
+                    InsertionResult::Overwritten
                 }
             },
             Self::Limited(lim) => {
@@ -51,6 +57,14 @@ impl<T: Hash + Eq> Reservoir<T> {
         }
     }
 
+    /// Returns whether item was in the sample
+    pub fn remove(&mut self, val: &T) -> bool {
+        match self {
+            Self::Unlimited(buf) => buf.swap_remove(val),
+            Self::Limited(lim) => lim.remove(val),
+        }
+    }
+
     #[must_use]
     pub fn inner(&self) -> &IndexSet<T> {
         match self {
@@ -58,6 +72,15 @@ impl<T: Hash + Eq> Reservoir<T> {
             Self::Unlimited(buf) => buf,
         }
     }
+
+    /// Number of items ever inserted and not removed, including ones that are not in the sample
+    #[must_use]
+    pub fn total_count(&self) -> usize {
+        match self {
+            Self::Limited(lim) => lim.total_count(),
+            Self::Unlimited(buf) => buf.len(),
+        }
+    }
 }
 
 #[derive(Derivative, Clone)]
@@ -101,6 +124,11 @@ impl<T> Limited<T> {
     pub fn max_size(&self) -> usize {
         self.buf.len() + self.fullness
     }
+
+    #[must_use]
+    pub const fn total_count(&self) -> usize {
+        self.total_count
+    }
 }
 
 impl<T: Hash + Eq> Limited<T> {
@@ -128,6 +156,9 @@ impl<T: Hash + Eq> Limited<T> {
     }
 
     pub fn insert(&mut self, val: T) -> InsertionResult<T> {
+        if self.buf.contains(&val) {
+            return InsertionResult::Overwritten
+        }
         self.total_count += 1;
         match self.fullness {
             0 => self.replace(val),
@@ -135,8 +166,25 @@ impl<T: Hash + Eq> Limited<T> {
         }
     }
 
+    /// Forgets about item that was inserted before. Returns whether it was in the sample
+    pub fn remove(&mut self, val: &T) -> bool {
+        self.total_count = self.total_count.saturating_sub(1);
+        if self.buf.swap_remove(val) {
+            self.fullness += 1;
+            true
+        } else {
+            false
+        }
+    }
+
     fn replace(&mut self, val: T) -> InsertionResult<T> {
         debug_assert_eq!(self.fullness, 0);
+        // Reservoir of size 0 keeps nothing
+        if self.buf.is_empty() {
+            return InsertionResult::Dropped {
+                val
+            }
+        }
         // Replace random item
         let idx = self.rng.gen_range(0, self.total_count - 1);
         if idx < self.buf.len() {
@@ -214,6 +262,28 @@ mod test {
         assert_eq!(empty.fullness, 41);
     }
 
+    #[test]
+    fn insert_existing() {
+        let mut res = Reservoir::Unlimited(IndexSet::new());
+        assert!(matches!(res.insert(7), InsertionResult::Inserted));
+        assert!(matches!(res.insert(7), InsertionResult::Overwritten));
+        assert!(matches!(res.insert(8), InsertionResult::Inserted));
+
+        let mut res = Reservoir::new(Size::Maximum(3), IndexSet::new());
+        assert!(matches!(res.insert(7), InsertionResult::Inserted));
+        assert!(matches!(res.insert(7), InsertionResult::Overwritten));
+        assert_eq!(res.total_count(), 1);
+    }
+
+    #[test]
+    fn zero_size() {
+        let mut res = Limited::new(0, IndexSet::new());
+        assert!(matches!(res.insert(7), InsertionResult::Dropped { val: 7 }));
+        assert!(matches!(res.insert(8), InsertionResult::Dropped { val: 8 }));
+        assert!(res.inner().is_empty());
+        assert_eq!(res.total_count, 2);
+    }
+
     #[test]
     fn overfull() {
         // FIXME: Too complicated test because of randomness
@@ -251,6 +321,24 @@ mod test {
         }
     }
 
+    #[test]
+    fn remove() {
+        let mut res = Limited::new(3, IndexSet::new());
+        res.insert(3);
+        res.insert(7);
+        assert!(res.remove(&3));
+        assert_eq!(res.inner().iter().collect::<Vec<_>>(), vec![&7]);
+        assert_eq!((res.total_count, res.fullness), (1, 2));
+
+        let mut res = Reservoir::Unlimited(IndexSet::new());
+        assert!(matches!(res.insert(7), InsertionResult::Inserted));
+        assert!(matches!(res.insert(7), InsertionResult::Overwritten));
+        assert_eq!(res.total_count(), 1);
+        assert!(res.remove(&7));
+        assert!(!res.remove(&7));
+        assert_eq!(res.total_count(), 0);
+    }
+
     #[test]
     fn serde_direct() {
         let mut res = Limited::new(3, IndexSet::new());
@@ -1,4 +1,5 @@
 #[cfg(feature="heed")]
 pub mod heed;
+pub mod memory;
 #[cfg(test)]
 pub mod testdb;
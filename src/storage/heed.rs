@@ -1,40 +1,291 @@
 use crate::api::storage::*;
+use crate::api::schema::{Schema, OnIncompatible, Incompatibility, SchemaError};
+use crate::reservoir::Size;
 use heed::types::OwnedSlice;
+use heed::flags::Flags;
 use heed::EnvOpenOptions;
 use std::path::Path;
-use std::fs;
+use std::ops::Bound;
+use std::{fmt, fs, io};
+use thiserror::Error;
 
 type Data = heed::Database<OwnedSlice<u8>, OwnedSlice<u8>>;
 
 struct Databases {
     data: Data,
     children: Data,
+    /// Missing only when read-only database was created before the schema registry existed
+    meta: Option<Data>,
+    children_size: Size,
 }
 
-struct HeedDb {
+pub struct HeedDb {
     env: heed::Env,
-    dbs: Databases
+    dbs: Databases,
+    schema_warnings: Vec<Incompatibility>,
 }
 
 #[derive(Copy, Clone)]
-struct Transaction<'db, T> where T: 'db {
+pub struct Transaction<'db, T> where T: 'db {
     txn: T,
     dbs: &'db Databases
 }
 
-impl HeedDb {
-    fn new<P: AsRef<Path>>(path: P) -> Result<Self, ()> {
-        fs::create_dir_all(&path).map_err(|_| ())?;
-        let env = EnvOpenOptions::new().open(path).map_err(|_| ())?;
-        let storage = env.create_database(Some("storage")).map_err(|_| ())?;
-        let children = env.create_database(Some("children")).map_err(|_| ())?;
-        Ok(HeedDb {
+// heed types have no `Debug`, so only our own settings are shown
+impl fmt::Debug for HeedDb {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HeedDb")
+            .field("children_size", &self.dbs.children_size)
+            .field("schema_warnings", &self.schema_warnings)
+            .finish()
+    }
+}
+
+impl<'db, T> fmt::Debug for Transaction<'db, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Transaction")
+            .field("children_size", &self.dbs.children_size)
+            .finish()
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum OpenError {
+    #[error("unable to create database directory: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("LMDB error: {0}")]
+    Heed(#[from] heed::Error),
+    /// Database was opened as read-only, but sub-database was never created
+    #[error("sub-database {0:?} does not exist")]
+    NoSuchDatabase(String),
+    #[error("schema registry is broken: {0}")]
+    Registry(Box<dyn std::error::Error + Send + Sync>),
+    #[error("database is incompatible with schema: {0:?}")]
+    Incompatible(Vec<Incompatibility>),
+    /// `Size::Maximum(0)` was passed to `HeedDbBuilder::children_size`
+    #[error("nodes must remember at least one child")]
+    ZeroChildrenSize,
+    /// Two storages were given the same sub-database name, so they would overwrite each other
+    #[error("sub-database {0:?} is used by several storages")]
+    DuplicateName(String),
+}
+
+/// Error of `Schema::apply`
+impl From<SchemaError<heed::Error, heed::Error, heed::Error, heed::Error>> for OpenError {
+    fn from(e: SchemaError<heed::Error, heed::Error, heed::Error, heed::Error>) -> Self {
+        match e {
+            SchemaError::Txn(TxnError::Begin(e))
+            | SchemaError::Txn(TxnError::Commit(e))
+            | SchemaError::Get(GetError::Other(e))
+            | SchemaError::Set(SetError::Other(e)) => Self::Heed(e),
+            // Boxed errors are not `Send`, so only the message is kept
+            SchemaError::Get(GetError::DeserializationError(e))
+            | SchemaError::Set(SetError::SerializationError(e)) => Self::Registry(e.to_string().into()),
+            SchemaError::Get(GetError::NoSuchPath) | SchemaError::Set(_) => unreachable!("registry is not a tree"),
+            SchemaError::Incompatible(found) => Self::Incompatible(found),
+        }
+    }
+}
+
+/// Error of `Schema::check`, which never commits and never writes.
+/// `!` can't be converted into `heed::Error` by `Into`, so it is matched away here
+impl From<SchemaError<heed::Error, !, heed::Error, !>> for OpenError {
+    fn from(e: SchemaError<heed::Error, !, heed::Error, !>) -> Self {
+        // `TxnError::Commit` holds `!`, so `exhaustive_patterns` lets us skip it
+        match e {
+            SchemaError::Txn(TxnError::Begin(e)) | SchemaError::Get(GetError::Other(e)) => Self::Heed(e),
+            SchemaError::Get(GetError::DeserializationError(e)) => Self::Registry(e.to_string().into()),
+            SchemaError::Get(GetError::NoSuchPath) => unreachable!("registry is not a tree"),
+            SchemaError::Set(_) => unreachable!("check never writes"),
+            SchemaError::Incompatible(found) => Self::Incompatible(found),
+        }
+    }
+}
+
+/// Options for opening `HeedDb`. Everything that is not set is left with LMDB defaults.
+#[derive(Debug, Clone)]
+pub struct HeedDbBuilder {
+    map_size: Option<usize>,
+    max_readers: Option<u32>,
+    data_name: String,
+    children_name: String,
+    meta_name: String,
+    read_only: bool,
+    no_sync: bool,
+    no_meta_sync: bool,
+    children_size: Size,
+    schema: Option<(Schema, OnIncompatible)>,
+}
+
+impl Default for HeedDbBuilder {
+    fn default() -> Self {
+        Self {
+            map_size: None,
+            max_readers: None,
+            data_name: "storage".to_string(),
+            children_name: "children".to_string(),
+            meta_name: "meta".to_string(),
+            read_only: false,
+            no_sync: false,
+            no_meta_sync: false,
+            children_size: Size::All,
+            schema: None,
+        }
+    }
+}
+
+impl HeedDbBuilder {
+    /// Maximum size of the database in bytes. Should be a multiple of the OS page size.
+    pub fn map_size(&mut self, size: usize) -> &mut Self {
+        self.map_size = Some(size);
+        self
+    }
+
+    /// Maximum number of simultaneous read transactions
+    pub fn max_readers(&mut self, readers: u32) -> &mut Self {
+        self.max_readers = Some(readers);
+        self
+    }
+
+    /// Name of the sub-database for `Storage::Data`. Names of all storages must differ
+    pub fn data_name<S: Into<String>>(&mut self, name: S) -> &mut Self {
+        self.data_name = name.into();
+        self
+    }
+
+    /// Name of the sub-database for `Storage::Children`
+    pub fn children_name<S: Into<String>>(&mut self, name: S) -> &mut Self {
+        self.children_name = name.into();
+        self
+    }
+
+    /// Name of the sub-database for `Storage::Meta`
+    pub fn meta_name<S: Into<String>>(&mut self, name: S) -> &mut Self {
+        self.meta_name = name.into();
+        self
+    }
+
+    /// Open environment as read-only. Data and children sub-databases must already exist,
+    /// missing meta sub-database is treated as empty.
+    pub fn read_only(&mut self, read_only: bool) -> &mut Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Don't flush buffers on commit (`MDB_NOSYNC`). Last transactions may be lost on system crash.
+    pub fn no_sync(&mut self, no_sync: bool) -> &mut Self {
+        self.no_sync = no_sync;
+        self
+    }
+
+    /// Don't flush meta page on commit (`MDB_NOMETASYNC`). Last transaction may be lost on system crash.
+    pub fn no_meta_sync(&mut self, no_meta_sync: bool) -> &mut Self {
+        self.no_meta_sync = no_meta_sync;
+        self
+    }
+
+    /// How many children are remembered by each node. `Size::Maximum(0)` is rejected by `open`
+    pub fn children_size(&mut self, size: Size) -> &mut Self {
+        self.children_size = size;
+        self
+    }
+
+    /// Compare database with the schema on open and record it (unless database is read-only).
+    /// With `OnIncompatible::Warn` found problems are available in `HeedDb::schema_warnings`
+    pub fn schema(&mut self, schema: Schema, on_incompatible: OnIncompatible) -> &mut Self {
+        self.schema = Some((schema, on_incompatible));
+        self
+    }
+
+    pub fn open<P: AsRef<Path>>(&self, path: P) -> Result<HeedDb, OpenError> {
+        if self.children_size == Size::Maximum(0) {
+            return Err(OpenError::ZeroChildrenSize)
+        }
+        if self.data_name == self.children_name || self.data_name == self.meta_name {
+            return Err(OpenError::DuplicateName(self.data_name.clone()))
+        }
+        if self.children_name == self.meta_name {
+            return Err(OpenError::DuplicateName(self.children_name.clone()))
+        }
+        if !self.read_only {
+            fs::create_dir_all(&path)?;
+        }
+
+        let mut options = EnvOpenOptions::new();
+        // data, children and meta
+        options.max_dbs(3);
+        if let Some(size) = self.map_size {
+            options.map_size(size);
+        }
+        if let Some(readers) = self.max_readers {
+            options.max_readers(readers);
+        }
+        // These flags are unsafe only because they may cause data loss on system crash
+        // or because changing them requires care from other processes.
+        unsafe {
+            if self.read_only {
+                options.flag(Flags::MdbRdOnly);
+            }
+            if self.no_sync {
+                options.flag(Flags::MdbNoSync);
+            }
+            if self.no_meta_sync {
+                options.flag(Flags::MdbNoMetaSync);
+            }
+        }
+        let env = options.open(path)?;
+
+        let data = self.database(&env, &self.data_name)?;
+        let children = self.database(&env, &self.children_name)?;
+        // Databases created before the schema registry have no meta
+        let meta = if self.read_only {
+            env.open_database(Some(self.meta_name.as_str()))?
+        } else {
+            Some(env.create_database(Some(self.meta_name.as_str()))?)
+        };
+        let mut db = HeedDb {
             env,
             dbs: Databases {
-                data: storage,
-                children
-            }
-        })
+                data,
+                children,
+                meta,
+                children_size: self.children_size,
+            },
+            schema_warnings: Vec::new(),
+        };
+        db.schema_warnings = match &self.schema {
+            Some((schema, on_incompatible)) if self.read_only => schema.check(&db, *on_incompatible)?,
+            Some((schema, on_incompatible)) => schema.apply(&db, *on_incompatible)?,
+            None => Vec::new(),
+        };
+        Ok(db)
+    }
+
+    fn database(&self, env: &heed::Env, name: &str) -> Result<Data, OpenError> {
+        if self.read_only {
+            env.open_database(Some(name))?
+                .ok_or_else(|| OpenError::NoSuchDatabase(name.to_string()))
+        } else {
+            Ok(env.create_database(Some(name))?)
+        }
+    }
+}
+
+impl HeedDb {
+    #[must_use]
+    pub fn builder() -> HeedDbBuilder {
+        HeedDbBuilder::default()
+    }
+
+    /// Opens database with default options, creating directory when needed
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, OpenError> {
+        Self::builder().open(path)
+    }
+
+    /// Incompatibilities with schema found on open
+    #[must_use]
+    pub fn schema_warnings(&self) -> &[Incompatibility] {
+        &self.schema_warnings
     }
 }
 
@@ -42,18 +293,20 @@ impl<'db> Database<'db> for HeedDb {
     type RoTxn = Transaction<'db, heed::RoTxn>;
     type RwTxn = Transaction<'db, heed::RwTxn<'db>>;
 
-    fn ro(&'db self) -> Self::RoTxn {
-        Transaction {
-            txn: self.env.read_txn().unwrap(),
+    type TxnErr = heed::Error;
+
+    fn ro(&'db self) -> Result<Self::RoTxn, Self::TxnErr> {
+        Ok(Transaction {
+            txn: self.env.read_txn()?,
             dbs: &self.dbs
-        }
+        })
     }
 
-    fn rw(&'db self) -> Self::RwTxn {
-        Transaction {
-            txn: self.env.write_txn().unwrap(),
+    fn rw(&'db self) -> Result<Self::RwTxn, Self::TxnErr> {
+        Ok(Transaction {
+            txn: self.env.write_txn()?,
             dbs: &self.dbs
-        }
+        })
     }
 }
 
@@ -68,14 +321,21 @@ impl<'a> Readable for heed::RwTxn<'a> {
 }
 
 impl Storage {
-    fn get_db(self, dbs: &Databases) -> &Data {
+    /// `None` when sub-database does not exist, which is possible only in read-only mode
+    fn get_db(self, dbs: &Databases) -> Option<&Data> {
         match self {
-            Storage::Data => &dbs.data,
-            Storage::Children => &dbs.children,
+            Storage::Data => Some(&dbs.data),
+            Storage::Children => Some(&dbs.children),
+            Storage::Meta => dbs.meta.as_ref(),
         }
     }
 }
 
+// Missing sub-databases exist only in read-only mode, where writes are impossible anyway
+fn read_only_error() -> heed::Error {
+    heed::Error::Io(io::Error::new(io::ErrorKind::PermissionDenied, "database is opened as read-only"))
+}
+
 impl<'db, T: Readable> CanRead for Transaction<'db, T> {
     type ExistsErr = heed::Error;
     fn exists(&self, storage: Storage, path: &[u8]) -> Result<bool, Self::ExistsErr> {
@@ -89,22 +349,144 @@ impl<'db, T: Readable> CanRead for Transaction<'db, T> {
 
     type GetErr = heed::Error;
     fn get(&self, storage: Storage, path: &[u8]) -> Result<Vec<u8>, GetError<Self::GetErr>> {
-        let res = storage.get_db(self.dbs)
-            .get(self.txn.readable(), path);
+        let db = match storage.get_db(self.dbs) {
+            Some(db) => db,
+            None => return Err(GetError::NoSuchPath),
+        };
+        let res = db.get(self.txn.readable(), path);
         match res {
             Ok(Some(x)) => Ok(x),
             Ok(None) => Err(GetError::NoSuchPath),
             Err(e) => Err(GetError::Other(e))
         }
     }
+
+    type IterErr = heed::Error;
+    fn range(&self, storage: Storage, start: Bound<&[u8]>, end: Bound<&[u8]>, direction: Direction)
+        -> Result<Entries<'_, Self::IterErr>, Self::IterErr>
+    {
+        let db = match storage.get_db(self.dbs) {
+            Some(db) => db,
+            None => return Ok(Box::new(std::iter::empty())),
+        };
+        match direction {
+            Direction::Forward => Ok(Box::new(db.range(self.txn.readable(), &(start, end))?)),
+            Direction::Backward => Ok(Box::new(Backward {
+                db,
+                txn: self.txn.readable(),
+                start: owned(start),
+                end: owned(end),
+                finished: false,
+            })),
+        }
+    }
+}
+
+type Entry = (Vec<u8>, Vec<u8>);
+
+fn owned(bound: Bound<&[u8]>) -> Bound<Vec<u8>> {
+    match bound {
+        Bound::Included(x) => Bound::Included(x.to_vec()),
+        Bound::Excluded(x) => Bound::Excluded(x.to_vec()),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+fn borrowed(bound: &Bound<Vec<u8>>) -> Bound<&[u8]> {
+    match bound {
+        Bound::Included(x) => Bound::Included(x),
+        Bound::Excluded(x) => Bound::Excluded(x),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+// Greater of two lower bounds
+fn max_lower<'a>(a: Bound<&'a [u8]>, b: &'a [u8]) -> Bound<&'a [u8]> {
+    match a {
+        Bound::Included(x) | Bound::Excluded(x) if x >= b => a,
+        _ => Bound::Included(b),
+    }
+}
+
+/// Last entry in the range, found with `seek` that returns the first one.
+/// Key is built byte by byte: each next byte is the greatest one some key in the range continues with,
+/// found by binary search. So it takes a few seeks per byte of the key and no memory beyond the key.
+fn last_in<E, F>(seek: &mut F, start: Bound<&[u8]>, end: Bound<&[u8]>) -> Result<Option<Entry>, E> where
+    F: FnMut(Bound<&[u8]>, Bound<&[u8]>) -> Result<Option<Entry>, E>,
+{
+    let mut key = Vec::new();
+    loop {
+        let len = key.len();
+        // Byte after `key` in the first key of the range that continues `key` with `byte` or greater
+        let mut next_byte = |key: &mut Vec<u8>, byte: u8| -> Result<Option<u8>, E> {
+            key.push(byte);
+            let found = seek(max_lower(start, key), end);
+            key.pop();
+            Ok(found?.and_then(|(k, _)| if k.starts_with(key) { k.get(len).copied() } else { None }))
+        };
+        let mut low = match next_byte(&mut key, 0)? {
+            Some(byte) => byte,
+            None => break,
+        };
+        // Some key continues with `low`, none continues with `high` or greater
+        let mut high = 0x100_u16;
+        while high - u16::from(low) > 1 {
+            #[allow(clippy::cast_possible_truncation)]  // Less than `high`
+            let mid = ((u16::from(low) + high) / 2) as u8;
+            match next_byte(&mut key, mid)? {
+                Some(byte) => low = byte,
+                None => high = u16::from(mid),
+            }
+        }
+        key.push(low);
+    }
+    // Nothing continues `key`, so it is either the last key itself or the range is empty
+    let found = seek(max_lower(start, &key), end)?;
+    Ok(found.filter(|(k, _)| *k == key))
+}
+
+/// heed 0.7 has only forward cursors, so each previous key is looked up with `last_in`
+struct Backward<'txn> {
+    db: &'txn Data,
+    txn: &'txn heed::RoTxn,
+    start: Bound<Vec<u8>>,
+    /// Excludes everything returned so far
+    end: Bound<Vec<u8>>,
+    finished: bool,
+}
+
+impl<'txn> Iterator for Backward<'txn> {
+    type Item = Result<Entry, heed::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None
+        }
+        let (db, txn) = (self.db, self.txn);
+        let mut seek = |start: Bound<&[u8]>, end: Bound<&[u8]>| db.range(txn, &(start, end))?.next().transpose();
+        match last_in(&mut seek, borrowed(&self.start), borrowed(&self.end)) {
+            Ok(Some((key, value))) => {
+                self.end = Bound::Excluded(key.clone());
+                Some(Ok((key, value)))
+            },
+            Ok(None) => {
+                self.finished = true;
+                None
+            },
+            Err(e) => {
+                self.finished = true;
+                Some(Err(e))
+            },
+        }
+    }
 }
 
 impl<'db> CanWrite for Transaction<'db, heed::RwTxn<'db>> {
     type SetErr = heed::Error;
     fn set(&mut self, storage: Storage, path: &[u8], data: &[u8]) -> Result<(), SetError<Self::SetErr>> {
-        // FIXME: Test for parent to exist
         storage.get_db(self.dbs)
-            .put(&mut self.txn, path, data)
+            .ok_or_else(read_only_error)
+            .and_then(|db| db.put(&mut self.txn, path, data))
             .map_err(SetError::Other)?;
         Ok(())
     }
@@ -112,7 +494,8 @@ impl<'db> CanWrite for Transaction<'db, heed::RwTxn<'db>> {
     type RemoveErr = heed::Error;
     fn remove(&mut self, storage: Storage, path: &[u8]) -> Result<(), RemoveError<Self::RemoveErr>> {
         let res = storage.get_db(self.dbs)
-            .delete(&mut self.txn, path)
+            .ok_or_else(read_only_error)
+            .and_then(|db| db.delete(&mut self.txn, path))
             .map_err(RemoveError::Other)?;
         if res {
             Ok(())
@@ -120,4 +503,113 @@ impl<'db> CanWrite for Transaction<'db, heed::RwTxn<'db>> {
             Err( RemoveError::NoSuchPath)
         }
     }
+
+    fn children_size(&self) -> Size {
+        self.dbs.children_size
+    }
+
+    type CommitErr = heed::Error;
+    fn commit(self) -> Result<(), Self::CommitErr> {
+        self.txn.commit()
+    }
+
+    fn abort(self) {
+        self.txn.abort()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Directory is removed on drop
+    struct TempDir(std::path::PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("ttdb-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn read_only_without_meta() {
+        let dir = TempDir::new("without-meta");
+        {
+            // Databases created before the schema registry had no meta
+            let env = EnvOpenOptions::new().max_dbs(2).open(&dir.0).unwrap();
+            let data: Data = env.create_database(Some("storage")).unwrap();
+            let _children: Data = env.create_database(Some("children")).unwrap();
+            let mut txn = env.write_txn().unwrap();
+            data.put(&mut txn, &b"key"[..], &b"value"[..]).unwrap();
+            txn.commit().unwrap();
+        }
+
+        let db = HeedDb::builder().read_only(true).open(&dir.0).unwrap();
+        {
+            let txn = db.ro().unwrap();
+            assert_eq!(txn.get(Storage::Data, b"key").unwrap(), b"value");
+            assert!(matches!(txn.get(Storage::Meta, b"schema"), Err(GetError::NoSuchPath)));
+            assert_eq!(txn.prefix(Storage::Meta, b"", Direction::Forward).unwrap().count(), 0);
+        }
+        drop(db);
+
+        let db = HeedDb::builder()
+            .read_only(true)
+            .schema(Schema::new(), OnIncompatible::Refuse)
+            .open(&dir.0)
+            .unwrap();
+        assert!(db.schema_warnings().is_empty());
+    }
+
+    #[test]
+    fn backward() {
+        let dir = TempDir::new("backward");
+        let db = HeedDb::new(&dir.0).unwrap();
+        let mut txn = db.rw().unwrap();
+        let keys: &[&[u8]] = &[b"\0", b"\0Foo\0", b"\0Foo\0Bar\0", b"\0Foo\xFF", b"\0Foobar\0", b"\xFF\xFF", b"a"];
+        for key in keys {
+            txn.set(Storage::Data, key, key).unwrap();
+        }
+
+        let check = |start: Bound<&[u8]>, end: Bound<&[u8]>| {
+            let forward = txn.range(Storage::Data, start, end, Direction::Forward).unwrap();
+            let mut expected = forward.collect::<Result<Vec<_>, _>>().unwrap();
+            expected.reverse();
+            let backward = txn.range(Storage::Data, start, end, Direction::Backward).unwrap();
+            assert_eq!(backward.collect::<Result<Vec<_>, _>>().unwrap(), expected);
+        };
+        check(Bound::Unbounded, Bound::Unbounded);
+        check(Bound::Included(&b"\0Foo\0"[..]), Bound::Excluded(&b"\0Foo\x01"[..]));
+        check(Bound::Excluded(&b"\0"[..]), Bound::Included(&b"\0Foobar\0"[..]));
+        check(Bound::Excluded(&b"a"[..]), Bound::Unbounded);
+
+        let prefix = txn.prefix(Storage::Data, b"\0Foo\0", Direction::Backward).unwrap();
+        let keys: Vec<_> = prefix.map(|x| x.unwrap().0).collect();
+        assert_eq!(keys, vec![b"\0Foo\0Bar\0".to_vec(), b"\0Foo\0".to_vec()]);
+    }
+
+    #[test]
+    fn zero_children_size() {
+        let dir = TempDir::new("zero-children");
+        let res = HeedDb::builder().children_size(Size::Maximum(0)).open(&dir.0);
+        assert!(matches!(res, Err(OpenError::ZeroChildrenSize)));
+    }
+
+    #[test]
+    fn duplicate_names() {
+        let dir = TempDir::new("duplicate-names");
+        let res = HeedDb::builder().children_name("storage").open(&dir.0);
+        assert!(matches!(res, Err(OpenError::DuplicateName(name)) if name == "storage"));
+        let res = HeedDb::builder().meta_name("children").open(&dir.0);
+        assert!(matches!(res, Err(OpenError::DuplicateName(name)) if name == "children"));
+    }
 }
@@ -0,0 +1,325 @@
+use crate::api::storage::*;
+use crate::reservoir::Size;
+use im::OrdMap;
+use std::ops::Bound;
+use std::sync::{Mutex, MutexGuard, PoisonError, RwLock};
+use thiserror::Error;
+
+// Persistent map: clones are O(1) and share nodes, so snapshots don't make writes copy everything
+type Table = OrdMap<Vec<u8>, Vec<u8>>;
+
+#[derive(Debug, Clone, Default)]
+struct Tables {
+    data: Table,
+    children: Table,
+    meta: Table,
+}
+
+impl Tables {
+    fn table(&self, storage: Storage) -> &Table {
+        match storage {
+            Storage::Data => &self.data,
+            Storage::Children => &self.children,
+            Storage::Meta => &self.meta,
+        }
+    }
+
+    fn table_mut(&mut self, storage: Storage) -> &mut Table {
+        match storage {
+            Storage::Data => &mut self.data,
+            Storage::Children => &mut self.children,
+            Storage::Meta => &mut self.meta,
+        }
+    }
+}
+
+/// Database that lives in memory only. Useful for tests and ephemeral caches.
+///
+/// Read transactions see a snapshot taken when they were created.
+/// Write transactions are exclusive (just like in LMDB) and work on their own copy of the data,
+/// so nothing is visible to others until commit and everything is rolled back on drop.
+/// Copies are cheap: a write copies only the nodes on the way to the changed key.
+#[derive(Debug, Default)]
+pub struct MemoryDb {
+    // Last committed state. Readers are just cloning it
+    committed: RwLock<Tables>,
+    // Held by the write transaction during its whole lifetime
+    writer: Mutex<()>,
+    children_size: Size,
+}
+
+/// `Size::Maximum(0)` was passed to `MemoryDb::with_children_size`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+#[error("nodes must remember at least one child")]
+pub struct ZeroChildrenSize;
+
+#[derive(Debug, Clone)]
+pub struct RoTransaction {
+    tables: Tables,
+}
+
+#[derive(Debug)]
+pub struct RwTransaction<'db> {
+    db: &'db MemoryDb,
+    _writer: MutexGuard<'db, ()>,
+    // Changed in place, readers keep sharing the old nodes
+    tables: Tables,
+}
+
+impl MemoryDb {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Nodes will remember at most `size` children. `Size::Maximum(0)` is rejected, just like in `HeedDb`
+    pub fn with_children_size(size: Size) -> Result<Self, ZeroChildrenSize> {
+        if size == Size::Maximum(0) {
+            return Err(ZeroChildrenSize)
+        }
+        Ok(Self {
+            children_size: size,
+            ..Self::default()
+        })
+    }
+
+    fn snapshot(&self) -> Tables {
+        self.committed.read().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+}
+
+impl<'db> Database<'db> for MemoryDb {
+    type RoTxn = RoTransaction;
+    type RwTxn = RwTransaction<'db>;
+    type TxnErr = !;
+
+    fn ro(&'db self) -> Result<Self::RoTxn, !> {
+        Ok(RoTransaction {
+            tables: self.snapshot(),
+        })
+    }
+
+    fn rw(&'db self) -> Result<Self::RwTxn, !> {
+        // Lock before taking snapshot, otherwise we may lose changes of previous writer
+        let writer = self.writer.lock().unwrap_or_else(PoisonError::into_inner);
+        Ok(RwTransaction {
+            db: self,
+            _writer: writer,
+            tables: self.snapshot(),
+        })
+    }
+}
+
+fn exists(tables: &Tables, storage: Storage, path: &[u8]) -> bool {
+    tables.table(storage).contains_key(path)
+}
+
+fn get(tables: &Tables, storage: Storage, path: &[u8]) -> Result<Vec<u8>, GetError<!>> {
+    tables.table(storage)
+        .get(path)
+        .cloned()
+        .ok_or(GetError::NoSuchPath)
+}
+
+fn range<'a>(tables: &'a Tables, storage: Storage, start: Bound<&[u8]>, end: Bound<&[u8]>, direction: Direction)
+    -> Entries<'a, !>
+{
+    // Nothing can be inside of such ranges, and ordered maps may panic on them
+    let empty = match (start, end) {
+        (Bound::Excluded(s), Bound::Excluded(e)) => s >= e,
+        (Bound::Included(s), Bound::Included(e))
+        | (Bound::Included(s), Bound::Excluded(e))
+        | (Bound::Excluded(s), Bound::Included(e)) => s > e,
+        _ => false,
+    };
+    if empty {
+        return Box::new(std::iter::empty())
+    }
+
+    let iter = tables.table(storage)
+        .range::<_, [u8]>((start, end))
+        .map(|(k, v)| Ok::<_, !>((k.clone(), v.clone())));
+    match direction {
+        Direction::Forward => Box::new(iter),
+        Direction::Backward => Box::new(iter.rev()),
+    }
+}
+
+impl CanRead for RoTransaction {
+    type ExistsErr = !;
+    fn exists(&self, storage: Storage, path: &[u8]) -> Result<bool, Self::ExistsErr> {
+        Ok(exists(&self.tables, storage, path))
+    }
+
+    type GetErr = !;
+    fn get(&self, storage: Storage, path: &[u8]) -> Result<Vec<u8>, GetError<Self::GetErr>> {
+        get(&self.tables, storage, path)
+    }
+
+    type IterErr = !;
+    fn range(&self, storage: Storage, start: Bound<&[u8]>, end: Bound<&[u8]>, direction: Direction)
+        -> Result<Entries<'_, !>, !>
+    {
+        Ok(range(&self.tables, storage, start, end, direction))
+    }
+}
+
+impl<'db> CanRead for RwTransaction<'db> {
+    type ExistsErr = !;
+    fn exists(&self, storage: Storage, path: &[u8]) -> Result<bool, Self::ExistsErr> {
+        Ok(exists(&self.tables, storage, path))
+    }
+
+    type GetErr = !;
+    fn get(&self, storage: Storage, path: &[u8]) -> Result<Vec<u8>, GetError<Self::GetErr>> {
+        get(&self.tables, storage, path)
+    }
+
+    type IterErr = !;
+    fn range(&self, storage: Storage, start: Bound<&[u8]>, end: Bound<&[u8]>, direction: Direction)
+        -> Result<Entries<'_, !>, !>
+    {
+        Ok(range(&self.tables, storage, start, end, direction))
+    }
+}
+
+impl<'db> CanWrite for RwTransaction<'db> {
+    type SetErr = !;
+    fn set(&mut self, storage: Storage, path: &[u8], data: &[u8]) -> Result<(), SetError<Self::SetErr>> {
+        self.tables
+            .table_mut(storage)
+            .insert(path.to_vec(), data.to_vec());
+        Ok(())
+    }
+
+    type RemoveErr = !;
+    fn remove(&mut self, storage: Storage, path: &[u8]) -> Result<(), RemoveError<Self::RemoveErr>> {
+        if !exists(&self.tables, storage, path) {
+            return Err(RemoveError::NoSuchPath)
+        }
+        self.tables
+            .table_mut(storage)
+            .remove(path);
+        Ok(())
+    }
+
+    fn children_size(&self) -> Size {
+        self.db.children_size
+    }
+
+    type CommitErr = !;
+    fn commit(self) -> Result<(), Self::CommitErr> {
+        let mut committed = self.db.committed.write().unwrap_or_else(PoisonError::into_inner);
+        *committed = self.tables;
+        Ok(())
+    }
+
+    fn abort(self) {
+        // Our copy of tables is just dropped
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn commit() {
+        let db = MemoryDb::new();
+        let mut rw = db.rw().unwrap();
+        rw.set(Storage::Data, b"\0Foo\0", b"data").unwrap();
+        assert_eq!(rw.get(Storage::Data, b"\0Foo\0").unwrap(), b"data");
+        rw.commit().unwrap();
+
+        let ro = db.ro().unwrap();
+        assert_eq!(ro.get(Storage::Data, b"\0Foo\0").unwrap(), b"data");
+        // Storages are separated
+        assert!(!ro.exists(Storage::Children, b"\0Foo\0").unwrap());
+    }
+
+    #[test]
+    fn rollback_on_drop() {
+        let db = MemoryDb::new();
+        let mut rw = db.rw().unwrap();
+        rw.set(Storage::Data, b"\0Foo\0", b"data").unwrap();
+        drop(rw);
+
+        let ro = db.ro().unwrap();
+        assert!(matches!(ro.get(Storage::Data, b"\0Foo\0"), Err(GetError::NoSuchPath)));
+    }
+
+    #[test]
+    fn snapshot() {
+        let db = MemoryDb::new();
+        let before = db.ro().unwrap();
+
+        let mut rw = db.rw().unwrap();
+        rw.set(Storage::Data, b"\0Foo\0", b"data").unwrap();
+        rw.commit().unwrap();
+
+        // Transaction created before commit does not see changes
+        assert!(!before.exists(Storage::Data, b"\0Foo\0").unwrap());
+        assert!(db.ro().unwrap().exists(Storage::Data, b"\0Foo\0").unwrap());
+    }
+
+    #[test]
+    fn remove() {
+        let db = MemoryDb::new();
+        let mut rw = db.rw().unwrap();
+        rw.set(Storage::Children, b"\0Foo\0", b"data").unwrap();
+        rw.remove(Storage::Children, b"\0Foo\0").unwrap();
+        assert!(matches!(rw.remove(Storage::Children, b"\0Foo\0"), Err(RemoveError::NoSuchPath)));
+    }
+
+    #[test]
+    fn zero_children_size() {
+        assert_eq!(MemoryDb::with_children_size(Size::Maximum(0)).err(), Some(ZeroChildrenSize));
+        assert!(MemoryDb::with_children_size(Size::Maximum(1)).is_ok());
+    }
+
+    fn keys(entries: Entries<'_, !>) -> Vec<Vec<u8>> {
+        entries.map(|x| x.unwrap().0).collect()
+    }
+
+    #[test]
+    fn prefix() {
+        let db = MemoryDb::new();
+        let mut rw = db.rw().unwrap();
+        for key in &[&b"\0Foo\0"[..], b"\0Foo\0Bar\0", b"\0Foo\0Baz\0", b"\0Foobar\0", b"\0Spam\0"] {
+            rw.set(Storage::Data, key, b"").unwrap();
+        }
+
+        let forward = rw.prefix(Storage::Data, b"\0Foo\0", Direction::Forward).unwrap();
+        assert_eq!(keys(forward), vec![
+            b"\0Foo\0".to_vec(),
+            b"\0Foo\0Bar\0".to_vec(),
+            b"\0Foo\0Baz\0".to_vec(),
+        ]);
+
+        let backward = rw.prefix(Storage::Data, b"\0Foo\0", Direction::Backward).unwrap();
+        assert_eq!(keys(backward), vec![
+            b"\0Foo\0Baz\0".to_vec(),
+            b"\0Foo\0Bar\0".to_vec(),
+            b"\0Foo\0".to_vec(),
+        ]);
+
+        // Other storage is empty
+        assert!(keys(rw.prefix(Storage::Children, b"", Direction::Forward).unwrap()).is_empty());
+    }
+
+    #[test]
+    fn range() {
+        let db = MemoryDb::new();
+        let mut rw = db.rw().unwrap();
+        for key in &[b"a", b"b", b"c", b"d"] {
+            rw.set(Storage::Data, &key[..], b"").unwrap();
+        }
+
+        let iter = rw.range(Storage::Data, Bound::Excluded(&b"a"[..]), Bound::Included(&b"c"[..]), Direction::Forward).unwrap();
+        assert_eq!(keys(iter), vec![b"b".to_vec(), b"c".to_vec()]);
+
+        // Does not panic on inverted ranges
+        let iter = rw.range(Storage::Data, Bound::Excluded(&b"b"[..]), Bound::Excluded(&b"b"[..]), Direction::Forward).unwrap();
+        assert!(keys(iter).is_empty());
+    }
+}
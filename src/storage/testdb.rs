@@ -1,4 +1,5 @@
 use crate::api::storage::*;
+use std::ops::Bound;
 
 /// Database storage that only panic
 #[derive(Copy, Clone, Debug)]
@@ -9,13 +10,14 @@ pub struct PanicTxn;
 impl<'db> Database<'db> for PanicDb {
     type RoTxn = PanicTxn;
     type RwTxn = PanicTxn;
+    type TxnErr = !;
 
-    fn ro(&'db self) -> Self::RoTxn {
-        PanicTxn
+    fn ro(&'db self) -> Result<Self::RoTxn, !> {
+        Ok(PanicTxn)
     }
 
-    fn rw(&'db self) -> Self::RwTxn {
-        PanicTxn
+    fn rw(&'db self) -> Result<Self::RwTxn, !> {
+        Ok(PanicTxn)
     }
 }
 
@@ -29,6 +31,13 @@ impl<'db> CanRead for PanicTxn {
     fn get(&self, _storage: Storage, _path: &[u8]) -> Result<Vec<u8>, GetError<!>> {
         panic!("PanicDb will only panic")
     }
+
+    type IterErr = !;
+    fn range(&self, _storage: Storage, _start: Bound<&[u8]>, _end: Bound<&[u8]>, _direction: Direction)
+        -> Result<Entries<'_, !>, !>
+    {
+        panic!("PanicDb will only panic")
+    }
 }
 impl<'db> CanWrite for PanicTxn {
     type SetErr = !;
@@ -40,4 +49,13 @@ impl<'db> CanWrite for PanicTxn {
     fn remove(&mut self, _storage: Storage, _path: &[u8]) -> Result<(), RemoveError<!>> {
         panic!("PanicDb will only panic")
     }
+
+    type CommitErr = !;
+    fn commit(self) -> Result<(), !> {
+        panic!("PanicDb will only panic")
+    }
+
+    fn abort(self) {
+        panic!("PanicDb will only panic")
+    }
 }
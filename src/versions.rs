@@ -2,6 +2,20 @@ use serde::{Deserialize, Serialize};
 use std::error::Error;
 use std::convert::TryInto;
 
+pub mod format;
+use format::{Format, DefaultFormat};
+mod impls;
+pub use impls::DecodeError;
+pub mod patch;
+pub mod introspect;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+
+/// Derive-макрос, реализующий трейты цепочки. Альтернатива `chain!`.
+/// Список атрибутов описан в `ttdb_derive::Version`.
+#[cfg(feature = "derive")]
+pub use ttdb_derive::Version;
+
 /// Базовый трейт. Этот трейт реализуют все элементы цепочки версий.
 /// Если тип хочет его реализовать, то он должен написать,какой тип будет первой версией.
 /// По сути он означает, что тип принадлежит определенной цепочки версий.
@@ -61,17 +75,26 @@ impl<T> !NextVersionRef for T where T: LastVersion {}
 // К сожалению, Rust на данный момент не позволяет автоматически реализовать LastVersion для всех подходящих типов
 
 /// Макрос для описания цепочек, который реализует все необходимые трейты (по три на каждую версию)
+///
+/// Каждой версии можно дать стабильное имя: `chain!(Foo as "user.v1" => Bar as "user.v2")`.
+/// Оно сохраняется в схеме и показывается в ошибках вместо `std::any::type_name`.
+///
+/// Версии с `#auto` сохраняются в `DefaultFormat`, но формат можно выбрать для всей цепочки:
+/// `chain!(#auto Foo => #auto Bar; format = Json)`. Значения в других форматах по-прежнему загружаются.
 #[macro_export]
 macro_rules! chain {
     (
-        $(#$v1_auto:tt)? $v1:ty $(=> $(#$auto:tt)? $other:ty)*
+        $(#$v1_auto:tt)? $v1:ty $(as $v1_name:literal)? $(=> $(#$auto:tt)? $other:ty $(as $name:literal)?)*
+        $(; format = $fmt:ty)?
     ) => {
-        $crate::chain!(@iter [$v1] $(#$v1_auto)? $v1 $(=> $(#$auto)? $other)*);
+        $crate::chain!(@iter [$v1] [$($fmt)?] $(#$v1_auto)? $v1 $(as $v1_name)? $(=> $(#$auto)? $other $(as $name)?)*);
     };
     (
-        @iter [$first:ty] $(#$v1_auto:tt)? $v1:ty => $(#$v2_auto:tt)? $v2:ty $(=> $(#$auto:tt)? $other:ty)*
+        @iter [$first:ty] [$($fmt:ty)?] $(#$v1_auto:tt)? $v1:ty $(as $v1_name:literal)?
+            => $(#$v2_auto:tt)? $v2:ty $(as $v2_name:literal)?
+            $(=> $(#$auto:tt)? $other:ty $(as $name:literal)?)*
     ) => {
-        $crate::chain!(@impl [$first] $(#$v1_auto)? $v1);
+        $crate::chain!(@impl [$first] [$($fmt)?] $(#$v1_auto)? $v1 $(as $v1_name)?);
         impl $crate::versions::NextVersionRef for $v1 {
             type NextVersion = $v2;
         }
@@ -79,18 +102,30 @@ macro_rules! chain {
             type PrevVersion = $v1;
         }
 
-        $crate::chain!(@iter [$first] $(#$v2_auto)? $v2 $(=> $(#$auto)? $other)*);
+        $crate::chain!(@iter [$first] [$($fmt)?] $(#$v2_auto)? $v2 $(as $v2_name)? $(=> $(#$auto)? $other $(as $name)?)*);
     };
-    ( @iter [$first:ty] $(#$auto:tt)? $last:ty ) => {
-        $crate::chain!(@impl [$first] $(#$auto)? $last);
+    ( @iter [$first:ty] [$($fmt:ty)?] $(#$auto:tt)? $last:ty $(as $name:literal)? ) => {
+        $crate::chain!(@impl [$first] [$($fmt)?] $(#$auto)? $last $(as $name)?);
         impl $crate::versions::LastVersion for $last {
         }
     };
-    (@impl [$first:ty] #auto $only:ty) => {
+    (@impl [$first:ty] [] #auto $only:ty $(as $name:literal)?) => {
         impl $crate::versions::AutoSerde for $only {}
-        $crate::chain!(@impl [$first] $only);
+        $crate::chain!(@impl [$first] [] $only $(as $name)?);
+    };
+    (@impl [$first:ty] [$fmt:ty] #auto $only:ty $(as $name:literal)?) => {
+        impl $crate::versions::AutoSerde for $only {
+            type Format = $fmt;
+        }
+        $crate::chain!(@impl [$first] [] $only $(as $name)?);
+    };
+    (@impl [$first:ty] [$($fmt:ty)?] $only:ty as $name:literal) => {
+        impl $crate::versions::StableName for $only {
+            const STABLE_NAME: &'static str = $name;
+        }
+        $crate::chain!(@impl [$first] [] $only);
     };
-    (@impl [$first:ty] $only:ty) => {
+    (@impl [$first:ty] [$($fmt:ty)?] $only:ty) => {
         impl $crate::versions::Version for $only {
             type FirstVersion = $first;
         }
@@ -128,10 +163,28 @@ pub trait Counter {
     const TYPE_NAME: &'static str;
 }
 
+/// Стабильное имя версии, которое задается в `chain!` при помощи `Foo as "foo.v1"`
+pub trait StableName {
+    const STABLE_NAME: &'static str;
+}
+
+// Если стабильного имени нет, то используется `std::any::type_name`, которое может меняться
+trait TypeName {
+    const NAME: &'static str;
+}
+
+impl<T> TypeName for T {
+    default const NAME: &'static str = std::any::type_name::<Self>();
+}
+
+impl<T: StableName> TypeName for T {
+    const NAME: &'static str = T::STABLE_NAME;
+}
+
 // Аналогично приёму с LastVersionRef, по-умолчанию версия нулевая.
 impl<T> Counter for T where T: Version {
     default const VERSION: usize = 0;
-    default const TYPE_NAME: &'static str = std::any::type_name::<Self>();
+    default const TYPE_NAME: &'static str = <Self as TypeName>::NAME;
 }
 
 // У всех последующих версий они будут отличаться ровно на единицу
@@ -140,7 +193,7 @@ impl<T> Counter for T where
     <T as PrevVersionRef>::PrevVersion: Counter
 {
     const VERSION: usize = 1 + <Self as PrevVersionRef>::PrevVersion::VERSION;
-    const TYPE_NAME: &'static str = std::any::type_name::<Self>();
+    const TYPE_NAME: &'static str = <Self as TypeName>::NAME;
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -166,18 +219,93 @@ mod loader {
         fn downgrade(self) -> Result<Self::PrevVersion, Box<dyn Error>>;
     }
 
-    #[derive(Debug)]
+    #[derive(Debug, thiserror::Error)]
     pub enum LoadError {
+        #[error("version {version} is unknown, the latest one is {max}")]
         VersionTooBig {
             version: usize,
             max: usize
         },
+        #[error("migration failed: {0}")]
         Migration(Box<dyn Error>),
+        #[error("unable to load value: {0}")]
         Load(Box<dyn Error>),
+        #[error("no migration from version {from_version} to {to_name} (version {to_version})")]
         NoMigration {
             from_version: usize,
             to_version: usize,
             to_name: &'static str
+        },
+        #[error("broken version header: {0}")]
+        Header(Box<dyn Error>),
+    }
+
+    /// Сохраняет значение, добавляя перед ним номер версии (целым числом MessagePack),
+    /// чтобы другая версия программы смогла его мигрировать при загрузке.
+    pub fn save_versioned<T: Serde>(val: T) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut buf = Vec::new();
+        rmp::encode::write_uint(&mut buf, T::VERSION as u64)?;
+        buf.append(&mut val.save()?);
+        Ok(buf)
+    }
+
+    /// Разделяет данные, сохраненные `save_versioned`, на номер версии и само значение
+    pub fn split_version(mut data: Vec<u8>) -> Result<(usize, Vec<u8>), LoadError> {
+        let mut cur = std::io::Cursor::new(&data[..]);
+        let version: usize = rmp::decode::read_int(&mut cur)
+            .map_err(|e| LoadError::Header(e.into()))?;
+        #[allow(clippy::cast_possible_truncation)]  // Заголовок короче самих данных
+        let header_len = cur.position() as usize;
+        let payload = data.split_off(header_len);
+        Ok((version, payload))
+    }
+
+    /// Загружает значение, сохраненное `save_versioned`, и при необходимости мигрирует его в `T`
+    pub fn load_versioned<T: Serde>(data: Vec<u8>) -> Result<T, LoadError> {
+        let (version, payload) = split_version(data)?;
+        load(version, payload)
+    }
+
+    /// Значение, которое могло быть сохранено более новой версией программы
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum Opaque<T> {
+        Known(T),
+        /// Эта версия программе неизвестна, поэтому данные хранятся как есть
+        Unknown {
+            version: usize,
+            data: Vec<u8>,
+        },
+    }
+
+    impl<T: Serde> Opaque<T> {
+        /// То же, что и `load_versioned`, но никогда не возвращает `LoadError::VersionTooBig`
+        pub fn load_versioned(data: Vec<u8>) -> Result<Self, LoadError> {
+            let (version, payload) = split_version(data)?;
+            if version > <T as LastVersionRef>::LastVersion::VERSION {
+                Ok(Self::Unknown { version, data: payload })
+            } else {
+                load(version, payload).map(Self::Known)
+            }
+        }
+
+        /// Известное значение сохраняется через `save_versioned`, а неизвестное — ровно в том виде, в котором было
+        pub fn save_versioned(self) -> Result<Vec<u8>, Box<dyn Error>> {
+            match self {
+                Self::Known(val) => save_versioned(val),
+                Self::Unknown { version, mut data } => {
+                    let mut buf = Vec::new();
+                    rmp::encode::write_uint(&mut buf, version as u64)?;
+                    buf.append(&mut data);
+                    Ok(buf)
+                },
+            }
+        }
+
+        pub fn known(self) -> Option<T> {
+            match self {
+                Self::Known(val) => Some(val),
+                Self::Unknown { .. } => None,
+            }
         }
     }
 
@@ -285,21 +413,18 @@ mod loader {
     }
 }
 
-pub trait AutoSerde: Version + for<'de> Deserialize<'de> + Serialize {}
+pub trait AutoSerde: Version + for<'de> Deserialize<'de> + Serialize {
+    /// Формат сохраняемых значений. Значения в других включенных форматах тоже загружаются
+    type Format: Format = DefaultFormat;
+}
 
 impl<T: AutoSerde> Serde for T {
     fn save(self) -> Result<Vec<u8>, Box<dyn Error>> {
-        let val = rmpv::ext::to_value(self)?;
-        let mut buf = Vec::new();
-        rmpv::encode::write_value(&mut buf, &val)?;
-        Ok(buf)
+        format::save::<T::Format, _>(&self)
     }
 
     fn load(data: Vec<u8>) -> Result<Self, Box<dyn Error>> {
-        let mut cur = std::io::Cursor::new(data);
-        let val = rmpv::decode::read_value(&mut cur)?;
-        let res = rmpv::ext::from_value(val)?;
-        Ok(res)
+        format::load(&data)
     }
 }
 
@@ -330,6 +455,8 @@ impl_for_number!(
     u16; i16;
     u32; i32;
     u64; i64;
+    u128; i128;
+    f32; f64;
 );
 
 chain!(String);
@@ -343,7 +470,7 @@ impl Serde for String {
     }
 }
 
-chain!(Vec<u8>);
+// Сама цепочка реализована для всех векторов
 impl Serde for Vec<u8> {
     fn save(self) -> Result<Vec<u8>, Box<dyn Error>> {
         Ok(self)
@@ -481,4 +608,102 @@ mod test {
             })
         ));
     }
+
+    #[test]
+    fn versioned() {
+        let data = save_versioned(Foo).unwrap();
+        assert_eq!(data, vec![0, 1]);  // Version 0, then Foo itself
+        // Foo -> Bar -> Baz
+        let baz = load_versioned::<Baz>(data).unwrap();
+        assert_eq!(baz, Baz);
+
+        let data = save_versioned(Baz).unwrap();
+        let foo = load_versioned::<Foo>(data);
+        assert!(matches!(foo, Err(LoadError::NoMigration { .. })));
+
+        assert!(matches!(load_versioned::<Foo>(Vec::new()), Err(LoadError::Header(_))));
+    }
+
+    #[derive(Serialize, Deserialize)] struct Named;
+    #[derive(Serialize, Deserialize)] struct NamedNext;
+    chain!(#auto Named as "test.v1" => #auto NamedNext as "test.v2");
+    sa::const_assert_eq!(NamedNext::VERSION, 1);
+
+    #[test]
+    fn stable_names() {
+        assert_eq!(Named::TYPE_NAME, "test.v1");
+        assert_eq!(NamedNext::TYPE_NAME, "test.v2");
+        // Other types still use type_name
+        assert_eq!(Foo::TYPE_NAME, "ttdb::versions::test::Foo");
+
+        let err = load::<Named>(NamedNext::VERSION, Vec::new());
+        assert!(matches!(err, Err(LoadError::NoMigration { to_name: "test.v1", .. })));
+    }
+
+    #[test]
+    fn opaque() {
+        // Baz is known and can be downgraded: Bar <- Baz
+        let data = save_versioned(Baz).unwrap();
+        assert_eq!(Opaque::<Bar>::load_versioned(data).unwrap(), Opaque::Known(Bar));
+
+        // Baz is unknown to the program, which knows only about Only
+        let data = save_versioned(Baz).unwrap();
+        let opaque = Opaque::<Only>::load_versioned(data.clone()).unwrap();
+        assert_eq!(opaque, Opaque::Unknown { version: 2, data: vec![3] });
+        assert_eq!(opaque.save_versioned().unwrap(), data);
+    }
+}
+
+#[cfg(all(test, feature = "derive"))]
+mod test_derive {
+    extern crate static_assertions as sa;
+    use super::*;
+    use serde::{Serialize, Deserialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize, Version)]
+    #[version(auto, name = "derived.first")]
+    struct First(u8);
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize, Version)]
+    #[version(prev = First, upgrade = second_from_first, downgrade = second_to_first, auto)]
+    struct Second(u16);
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize, Version)]
+    #[version(prev = Second, upgrade = third_from_second, auto, last)]
+    struct Third(u32);
+
+    fn second_from_first(prev: First) -> Result<Second, Box<dyn Error>> {
+        Ok(Second(prev.0.into()))
+    }
+
+    fn second_to_first(cur: Second) -> Result<First, Box<dyn Error>> {
+        Ok(First(cur.0.try_into()?))
+    }
+
+    fn third_from_second(prev: Second) -> Result<Third, Box<dyn Error>> {
+        Ok(Third(prev.0.into()))
+    }
+
+    sa::const_assert_eq!(First::VERSION, 0);
+    sa::const_assert_eq!(Second::VERSION, 1);
+    sa::const_assert_eq!(Third::VERSION, 2);
+    sa::assert_type_eq_all!(<First as NextVersionRef>::NextVersion, Second);
+    sa::assert_type_eq_all!(<Third as PrevVersionRef>::PrevVersion, Second);
+    sa::assert_type_eq_all!(First, <Third as Version>::FirstVersion);
+    sa::assert_impl_all!(Third: LastVersion);
+
+    #[test]
+    fn derived() {
+        assert_eq!(First::TYPE_NAME, "derived.first");
+
+        let data = save_versioned(First(42)).unwrap();
+        assert_eq!(load_versioned::<Third>(data).unwrap(), Third(42));
+
+        let data = save_versioned(Second(42)).unwrap();
+        assert_eq!(load_versioned::<First>(data).unwrap(), First(42));
+
+        // No downgrade from Third
+        let data = save_versioned(Third(42)).unwrap();
+        assert!(matches!(load_versioned::<Second>(data), Err(LoadError::NoMigration { .. })));
+    }
 }
@@ -0,0 +1,239 @@
+//! Форматы, в которых сохраняются значения `AutoSerde`.
+//!
+//! Каждое значение помнит свой формат, поэтому цепочка может в любой момент перейти на другой формат,
+//! а старые значения по-прежнему будут загружаться (пока их формат включен).
+//! Значения в `RmpvValue` сохраняются без маркера, то есть ровно так же, как до появления форматов.
+//! Остальные начинаются с `0xc1` (в MessagePack этот байт не используется), за которым следует `Format::ID`.
+//!
+//! Все форматы, кроме `RmpvValue`, включаются своими feature: `format-rmp-serde`, `format-json`,
+//! `format-bincode` или `format-cbor`.
+use serde::{de::DeserializeOwned, Serialize};
+use std::error::Error;
+
+const MARKER: u8 = 0xc1;
+
+/// Формат сериализации значений `AutoSerde`
+pub trait Format {
+    /// Сохраняется вместе со значением. Никогда не должен меняться
+    const ID: u8;
+    fn serialize<T: Serialize>(val: &T) -> Result<Vec<u8>, Box<dyn Error>>;
+    fn deserialize<T: DeserializeOwned>(data: &[u8]) -> Result<T, Box<dyn Error>>;
+}
+
+/// Формат `AutoSerde`, если цепочка не выбрала другой
+pub type DefaultFormat = RmpvValue;
+
+/// MessagePack через промежуточный `rmpv::Value`. Доступен всегда
+#[derive(Debug, Clone, Copy)]
+pub struct RmpvValue;
+
+impl Format for RmpvValue {
+    const ID: u8 = 0;
+
+    fn serialize<T: Serialize>(val: &T) -> Result<Vec<u8>, Box<dyn Error>> {
+        let val = rmpv::ext::to_value(val)?;
+        let mut buf = Vec::new();
+        rmpv::encode::write_value(&mut buf, &val)?;
+        Ok(buf)
+    }
+
+    fn deserialize<T: DeserializeOwned>(data: &[u8]) -> Result<T, Box<dyn Error>> {
+        let mut cur = std::io::Cursor::new(data);
+        let val = rmpv::decode::read_value(&mut cur)?;
+        let res = rmpv::ext::from_value(val)?;
+        Ok(res)
+    }
+}
+
+/// MessagePack, который напрямую пишет `rmp-serde`
+#[cfg(feature = "format-rmp-serde")]
+#[derive(Debug, Clone, Copy)]
+pub struct RmpSerde;
+
+#[cfg(feature = "format-rmp-serde")]
+impl Format for RmpSerde {
+    const ID: u8 = 1;
+
+    fn serialize<T: Serialize>(val: &T) -> Result<Vec<u8>, Box<dyn Error>> {
+        Ok(rmp_serde::to_vec(val)?)
+    }
+
+    fn deserialize<T: DeserializeOwned>(data: &[u8]) -> Result<T, Box<dyn Error>> {
+        Ok(rmp_serde::from_read_ref(data)?)
+    }
+}
+
+#[cfg(feature = "format-json")]
+#[derive(Debug, Clone, Copy)]
+pub struct Json;
+
+#[cfg(feature = "format-json")]
+impl Format for Json {
+    const ID: u8 = 2;
+
+    fn serialize<T: Serialize>(val: &T) -> Result<Vec<u8>, Box<dyn Error>> {
+        Ok(serde_json::to_vec(val)?)
+    }
+
+    fn deserialize<T: DeserializeOwned>(data: &[u8]) -> Result<T, Box<dyn Error>> {
+        Ok(serde_json::from_slice(data)?)
+    }
+}
+
+#[cfg(feature = "format-bincode")]
+#[derive(Debug, Clone, Copy)]
+pub struct Bincode;
+
+#[cfg(feature = "format-bincode")]
+impl Format for Bincode {
+    const ID: u8 = 3;
+
+    fn serialize<T: Serialize>(val: &T) -> Result<Vec<u8>, Box<dyn Error>> {
+        Ok(bincode::serialize(val)?)
+    }
+
+    fn deserialize<T: DeserializeOwned>(data: &[u8]) -> Result<T, Box<dyn Error>> {
+        Ok(bincode::deserialize(data)?)
+    }
+}
+
+#[cfg(feature = "format-cbor")]
+#[derive(Debug, Clone, Copy)]
+pub struct Cbor;
+
+#[cfg(feature = "format-cbor")]
+impl Format for Cbor {
+    const ID: u8 = 4;
+
+    fn serialize<T: Serialize>(val: &T) -> Result<Vec<u8>, Box<dyn Error>> {
+        Ok(serde_cbor::to_vec(val)?)
+    }
+
+    fn deserialize<T: DeserializeOwned>(data: &[u8]) -> Result<T, Box<dyn Error>> {
+        Ok(serde_cbor::from_slice(data)?)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("value is saved in format {0}, which is unknown or not enabled")]
+pub struct UnknownFormat(pub u8);
+
+/// Сохраняет значение в формате `F`, запоминая, какой формат использован
+pub fn save<F: Format, T: Serialize>(val: &T) -> Result<Vec<u8>, Box<dyn Error>> {
+    if F::ID == RmpvValue::ID {
+        return F::serialize(val)
+    }
+    let mut buf = vec![MARKER, F::ID];
+    buf.append(&mut F::serialize(val)?);
+    Ok(buf)
+}
+
+/// Возвращает формат, в котором сохранены данные, и сами данные
+#[must_use]
+pub fn detect(data: &[u8]) -> (u8, &[u8]) {
+    match data {
+        [MARKER, id, payload @ ..] => (*id, payload),
+        _ => (RmpvValue::ID, data),
+    }
+}
+
+/// Загружает значение, сохраненное `save` в любом из включенных форматов
+pub fn load<T: DeserializeOwned>(data: &[u8]) -> Result<T, Box<dyn Error>> {
+    let (id, payload) = detect(data);
+    if id == RmpvValue::ID {
+        return RmpvValue::deserialize(payload)
+    }
+    #[cfg(feature = "format-rmp-serde")]
+    if id == RmpSerde::ID {
+        return RmpSerde::deserialize(payload)
+    }
+    #[cfg(feature = "format-json")]
+    if id == Json::ID {
+        return Json::deserialize(payload)
+    }
+    #[cfg(feature = "format-bincode")]
+    if id == Bincode::ID {
+        return Bincode::deserialize(payload)
+    }
+    #[cfg(feature = "format-cbor")]
+    if id == Cbor::ID {
+        return Cbor::deserialize(payload)
+    }
+    Err(UnknownFormat(id).into())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Item {
+        name: String,
+        count: u32,
+    }
+
+    fn item() -> Item {
+        Item { name: "foo".to_string(), count: 42 }
+    }
+
+    fn roundtrip<F: Format>() {
+        let data = save::<F, _>(&item()).unwrap();
+        assert_eq!(detect(&data).0, F::ID);
+        assert_eq!(load::<Item>(&data).unwrap(), item());
+    }
+
+    #[test]
+    fn legacy() {
+        // Exactly what AutoSerde was writing before
+        let val = rmpv::ext::to_value(item()).unwrap();
+        let mut data = Vec::new();
+        rmpv::encode::write_value(&mut data, &val).unwrap();
+
+        assert_eq!(save::<RmpvValue, _>(&item()).unwrap(), data);
+        assert_eq!(load::<Item>(&data).unwrap(), item());
+    }
+
+    #[test]
+    fn formats() {
+        roundtrip::<RmpvValue>();
+        #[cfg(feature = "format-rmp-serde")]
+        roundtrip::<RmpSerde>();
+        #[cfg(feature = "format-json")]
+        roundtrip::<Json>();
+        #[cfg(feature = "format-bincode")]
+        roundtrip::<Bincode>();
+        #[cfg(feature = "format-cbor")]
+        roundtrip::<Cbor>();
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Explicit(u8);
+    crate::chain!(#auto Explicit; format = RmpvValue);
+    static_assertions::assert_type_eq_all!(<Explicit as crate::versions::AutoSerde>::Format, RmpvValue);
+
+    #[cfg(feature = "format-json")]
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct JsonFirst(u8);
+    #[cfg(feature = "format-json")]
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct JsonSecond(u8);
+    #[cfg(feature = "format-json")]
+    crate::chain!(#auto JsonFirst => #auto JsonSecond; format = Json);
+
+    #[test]
+    #[cfg(feature = "format-json")]
+    fn chain_format() {
+        use crate::versions::Serde;
+        let data = JsonSecond(7).save().unwrap();
+        assert_eq!(detect(&data).0, Json::ID);
+        assert_eq!(JsonSecond::load(data).unwrap(), JsonSecond(7));
+        assert_eq!(detect(&JsonFirst(7).save().unwrap()).0, Json::ID);
+    }
+
+    #[test]
+    fn unknown() {
+        let err = load::<Item>(&[MARKER, 200, 0]).unwrap_err();
+        assert_eq!(err.downcast_ref::<UnknownFormat>(), Some(&UnknownFormat(200)));
+    }
+}
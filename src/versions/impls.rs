@@ -0,0 +1,354 @@
+//! `Serde` для стандартных типов. Цепочка каждого из них состоит из единственной версии.
+//!
+//! Кодирование стабильно и не зависит от платформы:
+//! - `bool` занимает один байт, 0 или 1. `char` сохраняется как `u32`
+//! - Числа сохраняются байтами в little-endian
+//! - `Duration` — это секунды в `u64`, за которыми следуют наносекунды в `u32`
+//! - `SystemTime` — это секунды от начала эпохи UNIX в `i64`, за которыми следуют наносекунды в `u32`.
+//!   Наносекунды всегда прибавляются, так что 0.5 секунды до начала эпохи — это -1 секунда и 500 000 000 наносекунд
+//! - `Vec<u8>` и `String` сохраняются как есть
+//!
+//! Остальные типы состоят из элементов. Элемент — это его длина в `u64`, за которой следует сам элемент,
+//! сохраненный `save_versioned`, поэтому элементы из цепочек с несколькими версиями мигрируют при загрузке.
+//! - `Option<T>`: `None` не содержит элементов, а `Some` содержит один
+//! - Кортежи и `Vec<T>`: все элементы по порядку
+//! - `BTreeMap` и `HashMap`: ключ и значение каждой записи
+use super::{Version, LastVersion, Serde, save_versioned, load_versioned};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::{TryFrom, TryInto};
+use std::error::Error;
+use std::hash::{BuildHasher, Hash};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum DecodeError {
+    #[error("unexpected end of data")]
+    UnexpectedEnd,
+    #[error("unexpected data after the end of value")]
+    TrailingData,
+    #[error("invalid {0}")]
+    Invalid(&'static str),
+}
+
+macro_rules! single_version {
+    ([$($gen:tt)*] $ty:ty) => {
+        impl<$($gen)*> Version for $ty {
+            type FirstVersion = Self;
+        }
+        impl<$($gen)*> LastVersion for $ty {}
+    };
+}
+
+fn write_element<T: Serde>(buf: &mut Vec<u8>, val: T) -> Result<(), Box<dyn Error>> {
+    let data = save_versioned(val)?;
+    buf.extend_from_slice(&(data.len() as u64).to_le_bytes());
+    buf.extend_from_slice(&data);
+    Ok(())
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    const fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    const fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        if self.data.len() < len {
+            return Err(DecodeError::UnexpectedEnd)
+        }
+        let (res, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(res)
+    }
+
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        let mut res = [0; N];
+        res.copy_from_slice(self.take(N)?);
+        Ok(res)
+    }
+
+    fn element<T: Serde>(&mut self) -> Result<T, Box<dyn Error>> {
+        let len = usize::try_from(u64::from_le_bytes(self.bytes()?))?;
+        let data = self.take(len)?;
+        Ok(load_versioned(data.to_vec())?)
+    }
+
+    fn finish(self) -> Result<(), DecodeError> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(DecodeError::TrailingData)
+        }
+    }
+}
+
+single_version!([] bool);
+impl Serde for bool {
+    fn save(self) -> Result<Vec<u8>, Box<dyn Error>> {
+        Ok(vec![self.into()])
+    }
+
+    fn load(data: Vec<u8>) -> Result<Self, Box<dyn Error>> {
+        match data[..] {
+            [0] => Ok(false),
+            [1] => Ok(true),
+            _ => Err(DecodeError::Invalid("bool").into()),
+        }
+    }
+}
+
+single_version!([] char);
+impl Serde for char {
+    fn save(self) -> Result<Vec<u8>, Box<dyn Error>> {
+        u32::from(self).save()
+    }
+
+    fn load(data: Vec<u8>) -> Result<Self, Box<dyn Error>> {
+        let code = u32::load(data)?;
+        Self::try_from(code).map_err(|_| DecodeError::Invalid("char").into())
+    }
+}
+
+single_version!([] Duration);
+impl Serde for Duration {
+    fn save(self) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut buf = self.as_secs().to_le_bytes().to_vec();
+        buf.extend_from_slice(&self.subsec_nanos().to_le_bytes());
+        Ok(buf)
+    }
+
+    fn load(data: Vec<u8>) -> Result<Self, Box<dyn Error>> {
+        let mut reader = Reader::new(&data);
+        let secs = u64::from_le_bytes(reader.bytes()?);
+        let nanos = u32::from_le_bytes(reader.bytes()?);
+        reader.finish()?;
+        if nanos >= 1_000_000_000 {
+            return Err(DecodeError::Invalid("Duration").into())
+        }
+        Ok(Self::new(secs, nanos))
+    }
+}
+
+single_version!([] SystemTime);
+impl Serde for SystemTime {
+    fn save(self) -> Result<Vec<u8>, Box<dyn Error>> {
+        let (secs, nanos) = match self.duration_since(UNIX_EPOCH) {
+            Ok(after) => (i64::try_from(after.as_secs())?, after.subsec_nanos()),
+            Err(e) => {
+                let before = e.duration();
+                let secs = i64::try_from(before.as_secs())?;
+                match before.subsec_nanos() {
+                    0 => (-secs, 0),
+                    nanos => (-secs - 1, 1_000_000_000 - nanos),
+                }
+            },
+        };
+        let mut buf = secs.to_le_bytes().to_vec();
+        buf.extend_from_slice(&nanos.to_le_bytes());
+        Ok(buf)
+    }
+
+    #[allow(clippy::cast_sign_loss)]  // Знак проверен выше
+    fn load(data: Vec<u8>) -> Result<Self, Box<dyn Error>> {
+        let mut reader = Reader::new(&data);
+        let secs = i64::from_le_bytes(reader.bytes()?);
+        let nanos = u32::from_le_bytes(reader.bytes()?);
+        reader.finish()?;
+        if nanos >= 1_000_000_000 {
+            return Err(DecodeError::Invalid("SystemTime").into())
+        }
+        let res = if secs >= 0 {
+            UNIX_EPOCH.checked_add(Duration::new(secs as u64, nanos))
+        } else {
+            UNIX_EPOCH.checked_sub(Duration::from_secs(secs.wrapping_neg() as u64))
+                .and_then(|t| t.checked_add(Duration::from_nanos(nanos.into())))
+        };
+        res.ok_or_else(|| DecodeError::Invalid("SystemTime").into())
+    }
+}
+
+single_version!([T: Serde] Option<T>);
+impl<T: Serde> Serde for Option<T> {
+    fn save(self) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut buf = Vec::new();
+        if let Some(val) = self {
+            write_element(&mut buf, val)?;
+        }
+        Ok(buf)
+    }
+
+    fn load(data: Vec<u8>) -> Result<Self, Box<dyn Error>> {
+        let mut reader = Reader::new(&data);
+        if reader.is_empty() {
+            return Ok(None)
+        }
+        let res = reader.element()?;
+        reader.finish()?;
+        Ok(Some(res))
+    }
+}
+
+single_version!([T: Serde] Vec<T>);
+// Vec<u8> более специфичен и сохраняется как есть
+impl<T: Serde> Serde for Vec<T> {
+    default fn save(self) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut buf = Vec::new();
+        for val in self {
+            write_element(&mut buf, val)?;
+        }
+        Ok(buf)
+    }
+
+    default fn load(data: Vec<u8>) -> Result<Self, Box<dyn Error>> {
+        let mut reader = Reader::new(&data);
+        let mut res = Vec::new();
+        while !reader.is_empty() {
+            res.push(reader.element()?);
+        }
+        Ok(res)
+    }
+}
+
+single_version!([K: Serde + Ord, V: Serde] BTreeMap<K, V>);
+impl<K: Serde + Ord, V: Serde> Serde for BTreeMap<K, V> {
+    fn save(self) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut buf = Vec::new();
+        for (key, val) in self {
+            write_element(&mut buf, key)?;
+            write_element(&mut buf, val)?;
+        }
+        Ok(buf)
+    }
+
+    fn load(data: Vec<u8>) -> Result<Self, Box<dyn Error>> {
+        let mut reader = Reader::new(&data);
+        let mut res = Self::new();
+        while !reader.is_empty() {
+            let key = reader.element()?;
+            res.insert(key, reader.element()?);
+        }
+        Ok(res)
+    }
+}
+
+single_version!([K: Serde + Eq + Hash, V: Serde, S: BuildHasher + Default] HashMap<K, V, S>);
+impl<K: Serde + Eq + Hash, V: Serde, S: BuildHasher + Default> Serde for HashMap<K, V, S> {
+    fn save(self) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut buf = Vec::new();
+        for (key, val) in self {
+            write_element(&mut buf, key)?;
+            write_element(&mut buf, val)?;
+        }
+        Ok(buf)
+    }
+
+    fn load(data: Vec<u8>) -> Result<Self, Box<dyn Error>> {
+        let mut reader = Reader::new(&data);
+        let mut res = Self::default();
+        while !reader.is_empty() {
+            let key = reader.element()?;
+            res.insert(key, reader.element()?);
+        }
+        Ok(res)
+    }
+}
+
+macro_rules! impl_for_tuple {
+    ($($name:ident)+) => {
+        single_version!([$($name: Serde),+] ($($name,)+));
+        impl<$($name: Serde),+> Serde for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn save(self) -> Result<Vec<u8>, Box<dyn Error>> {
+                let ($($name,)+) = self;
+                let mut buf = Vec::new();
+                $(write_element(&mut buf, $name)?;)+
+                Ok(buf)
+            }
+
+            fn load(data: Vec<u8>) -> Result<Self, Box<dyn Error>> {
+                let mut reader = Reader::new(&data);
+                let res = ($(reader.element::<$name>()?,)+);
+                reader.finish()?;
+                Ok(res)
+            }
+        }
+    };
+    ( $($($name:ident)+);* $(;)? ) => {
+        $(impl_for_tuple!($($name)+);)*
+    };
+}
+
+impl_for_tuple!(
+    A;
+    A B;
+    A B C;
+    A B C D;
+    A B C D E;
+    A B C D E F;
+);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fmt::Debug;
+
+    fn roundtrip<T: Serde + Clone + PartialEq + Debug>(val: T) {
+        let data = val.clone().save().unwrap();
+        assert_eq!(T::load(data).unwrap(), val);
+    }
+
+    #[test]
+    fn encoding() {
+        assert_eq!(true.save().unwrap(), vec![1]);
+        assert_eq!('a'.save().unwrap(), vec![0x61, 0, 0, 0]);
+        assert_eq!(Duration::new(1, 2).save().unwrap(), vec![1, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0]);
+        assert_eq!(None::<u8>.save().unwrap(), Vec::<u8>::new());
+        // Length, version, value
+        assert_eq!(Some(7_u8).save().unwrap(), vec![2, 0, 0, 0, 0, 0, 0, 0, 0, 7]);
+        assert_eq!(vec![1_u8, 2].save().unwrap(), vec![1, 2]);
+
+        let before = UNIX_EPOCH - Duration::from_millis(500);
+        assert_eq!(before.save().unwrap(), vec![
+            0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+            0x00, 0x65, 0xcd, 0x1d,
+        ]);
+    }
+
+    #[test]
+    fn roundtrips() {
+        roundtrip(false);
+        roundtrip(1.5_f32);
+        roundtrip(-2.5_f64);
+        roundtrip(u128::max_value());
+        roundtrip(i128::min_value());
+        roundtrip('ы');
+        roundtrip(Duration::new(3, 999_999_999));
+        roundtrip(UNIX_EPOCH + Duration::new(1_600_000_000, 1));
+        roundtrip(UNIX_EPOCH - Duration::new(1, 1));
+        roundtrip(Some("foo".to_string()));
+        roundtrip(None::<String>);
+        roundtrip((1_u8, "foo".to_string(), Some(true)));
+        roundtrip(vec![vec![1_u16, 2], vec![], vec![3]]);
+        roundtrip(vec![1_u8, 2, 3]);
+
+        let map: BTreeMap<String, i32> = vec![("a".to_string(), 1), ("b".to_string(), -1)].into_iter().collect();
+        roundtrip(map.clone());
+        roundtrip(map.into_iter().collect::<HashMap<_, _>>());
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(bool::load(vec![2]).is_err());
+        assert!(char::load(0xd800_u32.save().unwrap()).is_err());
+        assert!(Duration::load(vec![0; 11]).is_err());
+        assert!(<(u8, u8)>::load((1_u8,).save().unwrap()).is_err());
+        assert!(<(u8,)>::load((1_u8, 2_u8).save().unwrap()).is_err());
+    }
+}
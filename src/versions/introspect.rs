@@ -0,0 +1,226 @@
+//! Описание всей цепочки во время выполнения, которое можно получить из любой ее версии.
+use super::{Version, NextVersionRef, PrevVersionRef, Upgradeable, Downgradeable, Serde};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VersionInfo {
+    pub version: usize,
+    pub name: &'static str,
+    /// Предыдущую версию можно обновить до этой
+    pub upgrade: bool,
+    /// Эту версию можно откатить до предыдущей
+    pub downgrade: bool,
+}
+
+/// Все версии цепочки, начиная с первой
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainDescriptor {
+    pub versions: Vec<VersionInfo>,
+}
+
+trait HasUpgrade {
+    const UPGRADE: bool;
+}
+
+impl<T> HasUpgrade for T {
+    default const UPGRADE: bool = false;
+}
+
+// Те же ограничения, что и у load_prev, иначе `load` на самом деле не пройдет через эту версию
+impl<T> HasUpgrade for T where
+    T: Serde + Upgradeable,
+    T::PrevVersion: Serde + NextVersionRef<NextVersion=T>,
+{
+    const UPGRADE: bool = true;
+}
+
+trait HasDowngrade {
+    const DOWNGRADE: bool;
+}
+
+impl<T> HasDowngrade for T {
+    default const DOWNGRADE: bool = false;
+}
+
+// Те же ограничения, что и у load_next предыдущей версии
+impl<T> HasDowngrade for T where
+    T: Serde + Downgradeable,
+    T::PrevVersion: Serde + NextVersionRef<NextVersion=T>,
+{
+    const DOWNGRADE: bool = true;
+}
+
+fn info<T: Version>() -> VersionInfo {
+    VersionInfo {
+        version: T::VERSION,
+        name: T::TYPE_NAME,
+        upgrade: <T as HasUpgrade>::UPGRADE,
+        downgrade: <T as HasDowngrade>::DOWNGRADE,
+    }
+}
+
+// Тот же прием, что и в load_prev и load_next: по умолчанию переходить некуда
+trait WalkPrev: Version {
+    /// Добавляет все версии до этой
+    fn walk_prev(res: &mut Vec<VersionInfo>);
+}
+
+impl<T: Version> WalkPrev for T {
+    default fn walk_prev(_res: &mut Vec<VersionInfo>) {}
+}
+
+impl<T> WalkPrev for T where
+    T: Version + PrevVersionRef,
+    T::PrevVersion: WalkPrev,
+{
+    fn walk_prev(res: &mut Vec<VersionInfo>) {
+        <T as PrevVersionRef>::PrevVersion::walk_prev(res);
+        res.push(info::<<T as PrevVersionRef>::PrevVersion>());
+    }
+}
+
+trait WalkNext: Version {
+    /// Добавляет все версии после этой
+    fn walk_next(res: &mut Vec<VersionInfo>);
+}
+
+impl<T: Version> WalkNext for T {
+    default fn walk_next(_res: &mut Vec<VersionInfo>) {}
+}
+
+impl<T> WalkNext for T where
+    T: Version + NextVersionRef,
+    T::NextVersion: WalkNext,
+{
+    fn walk_next(res: &mut Vec<VersionInfo>) {
+        res.push(info::<<T as NextVersionRef>::NextVersion>());
+        <T as NextVersionRef>::NextVersion::walk_next(res);
+    }
+}
+
+impl ChainDescriptor {
+    /// Описывает цепочку, к которой принадлежит `T`
+    #[must_use]
+    pub fn of<T: Version>() -> Self {
+        let mut versions = Vec::new();
+        T::walk_prev(&mut versions);
+        versions.push(info::<T>());
+        T::walk_next(&mut versions);
+        Self { versions }
+    }
+
+    /// Можно ли загрузить как версию `to` значение, сохраненное версией `from`
+    #[must_use]
+    pub fn can_migrate(&self, from: usize, to: usize) -> bool {
+        if from >= self.versions.len() || to >= self.versions.len() {
+            return false
+        }
+        if from <= to {
+            self.versions[from + 1..=to].iter().all(|v| v.upgrade)
+        } else {
+            self.versions[to + 1..=from].iter().all(|v| v.downgrade)
+        }
+    }
+
+    /// `matrix()[from][to]` — то же самое, что и `can_migrate(from, to)`
+    #[must_use]
+    pub fn matrix(&self) -> Vec<Vec<bool>> {
+        let len = self.versions.len();
+        (0..len)
+            .map(|from| (0..len).map(|to| self.can_migrate(from, to)).collect())
+            .collect()
+    }
+}
+
+/// Выводит версии, а за ними матрицу миграций, строки которой соответствуют исходным версиям
+impl fmt::Display for ChainDescriptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for v in &self.versions {
+            writeln!(f, "{}: {}", v.version, v.name)?;
+        }
+        write!(f, "   ")?;
+        for v in &self.versions {
+            write!(f, " {:>3}", v.version)?;
+        }
+        writeln!(f)?;
+        for (from, row) in self.matrix().into_iter().enumerate() {
+            write!(f, "{:>3}", from)?;
+            for can in row {
+                write!(f, " {:>3}", if can { "+" } else { "-" })?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::versions::Counter;
+    use serde::{Serialize, Deserialize};
+    use std::error::Error;
+
+    #[derive(Serialize, Deserialize)]
+    struct First;
+    #[derive(Serialize, Deserialize)]
+    struct Second;
+    #[derive(Serialize, Deserialize)]
+    struct Third;
+    crate::chain!(#auto First => #auto Second => #auto Third);
+
+    impl Upgradeable for Second {
+        fn upgrade(_prev: First) -> Result<Self, Box<dyn Error>> {
+            Ok(Self)
+        }
+    }
+
+    impl Upgradeable for Third {
+        fn upgrade(_prev: Second) -> Result<Self, Box<dyn Error>> {
+            Ok(Self)
+        }
+    }
+
+    impl Downgradeable for Third {
+        fn downgrade(self) -> Result<Second, Box<dyn Error>> {
+            Ok(Second)
+        }
+    }
+
+    #[test]
+    fn describe() {
+        let chain = ChainDescriptor::of::<Second>();
+        assert_eq!(chain, ChainDescriptor::of::<First>());
+        assert_eq!(chain, ChainDescriptor::of::<Third>());
+
+        let versions: Vec<_> = chain.versions.iter().map(|v| (v.version, v.upgrade, v.downgrade)).collect();
+        assert_eq!(versions, vec![(0, false, false), (1, true, false), (2, true, true)]);
+        assert_eq!(chain.versions[2].name, Third::TYPE_NAME);
+
+        assert_eq!(chain.matrix(), vec![
+            vec![true, true, true],
+            vec![false, true, true],
+            vec![false, true, true],
+        ]);
+        assert!(!chain.can_migrate(0, 3));
+    }
+
+    // Can't be loaded at all, so upgrade is useless
+    struct Unsaved;
+    #[derive(Serialize, Deserialize)]
+    struct Saved;
+    crate::chain!(Unsaved => #auto Saved);
+
+    impl Upgradeable for Saved {
+        fn upgrade(_prev: Unsaved) -> Result<Self, Box<dyn Error>> {
+            Ok(Self)
+        }
+    }
+
+    #[test]
+    fn unsaved_prev() {
+        let chain = ChainDescriptor::of::<Saved>();
+        assert!(!chain.versions[1].upgrade);
+        assert!(!chain.can_migrate(0, 1));
+    }
+}
@@ -0,0 +1,258 @@
+//! Обновления, описанные как преобразования `rmpv::Value`.
+//!
+//! Предыдущая версия больше не обязана быть настоящим типом, достаточно заглушки:
+//! ```ignore
+//! #[derive(Serialize, Deserialize)]
+//! #[serde(transparent)]
+//! struct UserV1(rmpv::Value);
+//!
+//! chain!(#auto UserV1 => #auto User);
+//!
+//! impl PatchUpgrade for User {
+//!     fn patch() -> Patch {
+//!         Patch::new()
+//!             .named(&["name", "age"])
+//!             .rename("age", "years")
+//!             .add("email", "")
+//!     }
+//! }
+//! ```
+//! Заглушку можно загрузить только из самоописывающих форматов, поэтому с `Bincode` она не сработает.
+use super::{AutoSerde, PrevVersionRef, Upgradeable};
+use rmpv::Value;
+use serde::Serialize;
+use std::error::Error;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Step {
+    Named(Vec<String>),
+    Rename(String, String),
+    Add(String, Value),
+    Drop(String),
+    Wrap(String),
+    At(String, Patch),
+}
+
+/// Последовательность изменений, которые применяются к значению по очереди
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Patch {
+    steps: Vec<Step>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PatchError {
+    #[error("expected map, found {0}")]
+    NotMap(Value),
+    #[error("expected {expected} fields, found {found}")]
+    FieldCount {
+        expected: usize,
+        found: usize,
+    },
+    #[error("no field {0}")]
+    NoField(String),
+    #[error("field {0}: {1}")]
+    At(String, Box<PatchError>),
+}
+
+fn as_map(val: &mut Value) -> Result<&mut Vec<(Value, Value)>, PatchError> {
+    match val {
+        Value::Map(map) => Ok(map),
+        other => Err(PatchError::NotMap(other.clone())),
+    }
+}
+
+fn position(map: &[(Value, Value)], name: &str) -> Result<usize, PatchError> {
+    map.iter()
+        .position(|(key, _)| key.as_str() == Some(name))
+        .ok_or_else(|| PatchError::NoField(name.to_string()))
+}
+
+impl Step {
+    fn apply(&self, mut val: Value) -> Result<Value, PatchError> {
+        match self {
+            Self::Named(names) => {
+                let items = match val {
+                    Value::Array(items) => items,
+                    // Имена уже есть
+                    Value::Map(_) => return Ok(val),
+                    other => return Err(PatchError::NotMap(other)),
+                };
+                if items.len() != names.len() {
+                    return Err(PatchError::FieldCount {
+                        expected: names.len(),
+                        found: items.len(),
+                    })
+                }
+                let map = names.iter()
+                    .map(|name| Value::from(name.as_str()))
+                    .zip(items)
+                    .collect();
+                return Ok(Value::Map(map))
+            },
+            Self::Rename(from, to) => {
+                let map = as_map(&mut val)?;
+                let i = position(map, from)?;
+                map[i].0 = Value::from(to.as_str());
+            },
+            Self::Add(name, default) => {
+                let map = as_map(&mut val)?;
+                if position(map, name).is_err() {
+                    map.push((Value::from(name.as_str()), default.clone()));
+                }
+            },
+            Self::Drop(name) => {
+                let map = as_map(&mut val)?;
+                let i = position(map, name)?;
+                map.remove(i);
+            },
+            Self::Wrap(name) => {
+                return Ok(Value::Map(vec![(Value::from(name.as_str()), val)]))
+            },
+            Self::At(name, patch) => {
+                let map = as_map(&mut val)?;
+                let i = position(map, name)?;
+                let field = std::mem::replace(&mut map[i].1, Value::Nil);
+                map[i].1 = patch.apply(field).map_err(|e| PatchError::At(name.clone(), Box::new(e)))?;
+            },
+        }
+        Ok(val)
+    }
+}
+
+impl Patch {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn step(mut self, step: Step) -> Self {
+        self.steps.push(step);
+        self
+    }
+
+    /// Дает имена полям структуры, сохраненной как массив. Словари остаются как есть
+    #[must_use]
+    pub fn named(self, names: &[&str]) -> Self {
+        self.step(Step::Named(names.iter().map(|&name| name.to_string()).collect()))
+    }
+
+    #[must_use]
+    pub fn rename(self, from: &str, to: &str) -> Self {
+        self.step(Step::Rename(from.to_string(), to.to_string()))
+    }
+
+    /// Добавляет поле, если его еще нет
+    #[must_use]
+    pub fn add(self, name: &str, default: impl Into<Value>) -> Self {
+        self.step(Step::Add(name.to_string(), default.into()))
+    }
+
+    #[must_use]
+    pub fn drop(self, name: &str) -> Self {
+        self.step(Step::Drop(name.to_string()))
+    }
+
+    /// Делает все значение единственным полем нового словаря
+    #[must_use]
+    pub fn wrap(self, name: &str) -> Self {
+        self.step(Step::Wrap(name.to_string()))
+    }
+
+    /// Применяет к полю другой патч
+    #[must_use]
+    pub fn at(self, name: &str, patch: Self) -> Self {
+        self.step(Step::At(name.to_string(), patch))
+    }
+
+    pub fn apply(&self, val: Value) -> Result<Value, PatchError> {
+        self.steps.iter().try_fold(val, |val, step| step.apply(val))
+    }
+}
+
+/// Обновление с предыдущей версии при помощи `Patch` вместо кода на Rust
+pub trait PatchUpgrade: AutoSerde + PrevVersionRef {
+    fn patch() -> Patch;
+}
+
+impl<T> Upgradeable for T where
+    T: PatchUpgrade,
+    <T as PrevVersionRef>::PrevVersion: Serialize,
+{
+    fn upgrade(prev: Self::PrevVersion) -> Result<Self, Box<dyn Error>> {
+        let val = rmpv::ext::to_value(prev)?;
+        let val = Self::patch().apply(val)?;
+        Ok(rmpv::ext::from_value(val)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::versions::{save_versioned, load_versioned};
+    use serde::Deserialize;
+
+    // Definition of the first version, which is not used anymore
+    #[derive(Serialize)]
+    struct Deleted {
+        name: String,
+        age: u32,
+        admin: bool,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    #[serde(transparent)]
+    struct UserV1(Value);
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Name {
+        first: String,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct User {
+        name: Name,
+        years: u32,
+        email: String,
+    }
+
+    crate::chain!(#auto UserV1 => #auto User);
+
+    impl PatchUpgrade for User {
+        fn patch() -> Patch {
+            Patch::new()
+                .named(&["name", "age", "admin"])
+                .rename("age", "years")
+                .drop("admin")
+                .add("email", "none")
+                .at("name", Patch::new().wrap("first"))
+        }
+    }
+
+    #[test]
+    fn upgrade() {
+        let old = Deleted {
+            name: "foo".to_string(),
+            age: 42,
+            admin: true,
+        };
+        let data = save_versioned(UserV1(rmpv::ext::to_value(old).unwrap())).unwrap();
+        assert_eq!(load_versioned::<User>(data).unwrap(), User {
+            name: Name { first: "foo".to_string() },
+            years: 42,
+            email: "none".to_string(),
+        });
+    }
+
+    #[test]
+    fn errors() {
+        let val = Value::Array(vec![Value::from(1)]);
+        let res = Patch::new().named(&["a", "b"]).apply(val.clone());
+        assert!(matches!(res, Err(PatchError::FieldCount { expected: 2, found: 1 })));
+
+        let res = Patch::new().rename("a", "b").apply(val.clone());
+        assert!(matches!(res, Err(PatchError::NotMap(_))));
+
+        let res = Patch::new().named(&["a"]).at("a", Patch::new().drop("b")).apply(val);
+        assert!(matches!(res, Err(PatchError::At(ref field, _)) if field == "a"));
+    }
+}
@@ -0,0 +1,219 @@
+//! Проверки всей цепочки на случайных значениях. Включаются feature `testing`.
+//!
+//! Каждая версия должна реализовывать `Serde`, `Arbitrary`, `Clone`, `PartialEq` и `Debug`.
+//! Все проверки паникуют, так что вызывать их предполагается из тестов:
+//! ```ignore
+//! #[test]
+//! fn user_chain() {
+//!     ttdb::versions::testing::check_chain::<UserV1>(100, 42);
+//! }
+//! ```
+use super::{Version, FirstVersion, NextVersionRef, PrevVersionRef, Upgradeable, Downgradeable};
+use super::{Serde, LoadError, load, save_versioned, load_versioned};
+use super::introspect::ChainDescriptor;
+use rand::{Rng, SeedableRng, rngs::SmallRng};
+use std::error::Error;
+use std::fmt::Debug;
+
+/// Генератор случайных значений
+pub trait Arbitrary: Sized {
+    fn arbitrary<R: Rng + ?Sized>(rng: &mut R) -> Self;
+}
+
+type Samples = Vec<(usize, Vec<u8>)>;
+
+fn check_version<T>(rng: &mut SmallRng, cases: usize, samples: &mut Samples) where
+    T: Serde + Arbitrary + Clone + PartialEq + Debug
+{
+    for _ in 0..cases {
+        let val = T::arbitrary(rng);
+        let data = val.clone().save().expect("unable to save value");
+        let loaded = T::load(data.clone()).expect("unable to load saved value");
+        assert_eq!(loaded, val, "{} changed after save and load", T::TYPE_NAME);
+
+        let versioned = save_versioned(val.clone()).expect("unable to save value");
+        let loaded = load_versioned::<T>(versioned).expect("unable to load saved value");
+        assert_eq!(loaded, val, "{} changed after save_versioned and load_versioned", T::TYPE_NAME);
+
+        samples.push((T::VERSION, data));
+    }
+}
+
+// Обновление, а затем откат, если существуют оба
+trait UpDown: PrevVersionRef {
+    fn up_down(prev: Self::PrevVersion) -> Option<Result<Self::PrevVersion, Box<dyn Error>>>;
+}
+
+impl<T: PrevVersionRef> UpDown for T {
+    default fn up_down(_prev: Self::PrevVersion) -> Option<Result<Self::PrevVersion, Box<dyn Error>>> {
+        None
+    }
+}
+
+impl<T: Upgradeable + Downgradeable> UpDown for T {
+    fn up_down(prev: Self::PrevVersion) -> Option<Result<Self::PrevVersion, Box<dyn Error>>> {
+        Some(T::upgrade(prev).and_then(T::downgrade))
+    }
+}
+
+// Проходит от первой версии до последней, так же как это делает introspect
+trait Generate: Version {
+    fn generate(rng: &mut SmallRng, cases: usize, samples: &mut Samples);
+}
+
+impl<T: Version> Generate for T {
+    default fn generate(_rng: &mut SmallRng, _cases: usize, _samples: &mut Samples) {
+        panic!("{} must implement Serde, Arbitrary, Clone, PartialEq and Debug", T::TYPE_NAME)
+    }
+}
+
+impl<T> Generate for T where
+    T: Version + Serde + Arbitrary + Clone + PartialEq + Debug
+{
+    default fn generate(rng: &mut SmallRng, cases: usize, samples: &mut Samples) {
+        check_version::<T>(rng, cases, samples);
+    }
+}
+
+impl<T> Generate for T where
+    T: Version + Serde + Arbitrary + Clone + PartialEq + Debug + NextVersionRef,
+    T::NextVersion: Generate + UpDown + PrevVersionRef<PrevVersion=T>,
+{
+    fn generate(rng: &mut SmallRng, cases: usize, samples: &mut Samples) {
+        check_version::<T>(rng, cases, samples);
+        for _ in 0..cases {
+            let val = T::arbitrary(rng);
+            if let Some(res) = <T::NextVersion as UpDown>::up_down(val.clone()) {
+                let res = res.unwrap_or_else(|e| panic!(
+                    "{} -> {} -> {} failed: {}",
+                    T::TYPE_NAME, T::NextVersion::TYPE_NAME, T::TYPE_NAME, e
+                ));
+                assert_eq!(res, val, "upgrade followed by downgrade changed {}", T::TYPE_NAME);
+            }
+        }
+        T::NextVersion::generate(rng, cases, samples);
+    }
+}
+
+fn check_loads<T: Serde>(samples: &[(usize, Vec<u8>)], chain: &ChainDescriptor) {
+    for (version, data) in samples {
+        let expected = chain.can_migrate(*version, T::VERSION);
+        match load::<T>(*version, data.clone()) {
+            Ok(_) if expected => {},
+            Err(LoadError::NoMigration { .. }) if !expected => {},
+            Ok(_) => panic!("version {} is loaded as {}, but there is no migration", version, T::TYPE_NAME),
+            Err(e) => panic!("unable to load version {} as {}: {}", version, T::TYPE_NAME, e),
+        }
+    }
+}
+
+trait LoadAll: Version {
+    fn load_all(samples: &[(usize, Vec<u8>)], chain: &ChainDescriptor);
+}
+
+impl<T: Version> LoadAll for T {
+    // На таких типах Generate уже упал
+    default fn load_all(_samples: &[(usize, Vec<u8>)], _chain: &ChainDescriptor) {}
+}
+
+impl<T: Version + Serde> LoadAll for T {
+    default fn load_all(samples: &[(usize, Vec<u8>)], chain: &ChainDescriptor) {
+        check_loads::<T>(samples, chain);
+    }
+}
+
+impl<T> LoadAll for T where
+    T: Version + Serde + NextVersionRef,
+    T::NextVersion: LoadAll,
+{
+    fn load_all(samples: &[(usize, Vec<u8>)], chain: &ChainDescriptor) {
+        check_loads::<T>(samples, chain);
+        T::NextVersion::load_all(samples, chain);
+    }
+}
+
+/// Проверяет цепочку, начиная с `T`, генерируя по `cases` значений каждой версии:
+/// - каждая версия не меняется после сохранения и загрузки
+/// - обновление, а затем откат возвращают то же самое значение
+/// - значение любой версии загружается как любая другая версия, если между ними есть миграция
+pub fn check_chain<T: FirstVersion>(cases: usize, seed: u64) {
+    let mut rng = SmallRng::seed_from_u64(seed);
+    let mut samples = Vec::new();
+    T::generate(&mut rng, cases, &mut samples);
+    T::load_all(&samples, &ChainDescriptor::of::<T>());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde::{Serialize, Deserialize};
+    use std::convert::TryInto;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)] struct V1(u8);
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)] struct V2(u16);
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)] struct V3(u32);
+    crate::chain!(#auto V1 => #auto V2 => #auto V3);
+
+    impl Upgradeable for V2 {
+        fn upgrade(prev: V1) -> Result<Self, Box<dyn Error>> {
+            Ok(Self(prev.0.into()))
+        }
+    }
+
+    impl Downgradeable for V2 {
+        fn downgrade(self) -> Result<V1, Box<dyn Error>> {
+            Ok(V1(self.0.try_into()?))
+        }
+    }
+
+    impl Upgradeable for V3 {
+        fn upgrade(prev: V2) -> Result<Self, Box<dyn Error>> {
+            Ok(Self(prev.0.into()))
+        }
+    }
+
+    impl Arbitrary for V1 {
+        fn arbitrary<R: Rng + ?Sized>(rng: &mut R) -> Self { Self(rng.gen()) }
+    }
+    // Only these can be downgraded
+    impl Arbitrary for V2 {
+        fn arbitrary<R: Rng + ?Sized>(rng: &mut R) -> Self { Self(rng.gen_range(0, 256)) }
+    }
+    impl Arbitrary for V3 {
+        fn arbitrary<R: Rng + ?Sized>(rng: &mut R) -> Self { Self(rng.gen()) }
+    }
+
+    #[test]
+    fn valid_chain() {
+        check_chain::<V1>(50, 1);
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)] struct Lossy1(u8);
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)] struct Lossy2(u8);
+    crate::chain!(#auto Lossy1 => #auto Lossy2);
+
+    impl Upgradeable for Lossy2 {
+        fn upgrade(prev: Lossy1) -> Result<Self, Box<dyn Error>> {
+            Ok(Self(prev.0 / 2))
+        }
+    }
+
+    impl Downgradeable for Lossy2 {
+        fn downgrade(self) -> Result<Lossy1, Box<dyn Error>> {
+            Ok(Lossy1(self.0 * 2))
+        }
+    }
+
+    impl Arbitrary for Lossy1 {
+        fn arbitrary<R: Rng + ?Sized>(rng: &mut R) -> Self { Self(rng.gen()) }
+    }
+    impl Arbitrary for Lossy2 {
+        fn arbitrary<R: Rng + ?Sized>(rng: &mut R) -> Self { Self(rng.gen_range(0, 128)) }
+    }
+
+    #[test]
+    #[should_panic(expected = "upgrade followed by downgrade")]
+    fn lossy_chain() {
+        check_chain::<Lossy1>(50, 1);
+    }
+}
@@ -0,0 +1,4 @@
+ttdb::path!(struct EmptyTuple(););
+ttdb::path!(struct EmptyNamed {};);
+
+fn main() {}
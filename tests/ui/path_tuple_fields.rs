@@ -0,0 +1,3 @@
+ttdb::path!(struct Big(u8, u8, u8, u8, u8, u8, u8, u8, u8););
+
+fn main() {}
@@ -0,0 +1,182 @@
+//! Derive macros for `ttdb`. Use them through `ttdb::versions::Version` with `derive` feature enabled.
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+use syn::{parse_macro_input, DeriveInput, Ident, LitStr, Token, Type};
+
+enum Arg {
+    Prev(Type),
+    Upgrade(syn::Path),
+    Downgrade(syn::Path),
+    Auto,
+    Format(Type),
+    Name(LitStr),
+    Last,
+}
+
+impl Parse for Arg {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let name: Ident = input.parse()?;
+        match name.to_string().as_str() {
+            "prev" => {
+                input.parse::<Token![=]>()?;
+                Ok(Self::Prev(input.parse()?))
+            },
+            "upgrade" => {
+                input.parse::<Token![=]>()?;
+                Ok(Self::Upgrade(input.parse()?))
+            },
+            "downgrade" => {
+                input.parse::<Token![=]>()?;
+                Ok(Self::Downgrade(input.parse()?))
+            },
+            "auto" => Ok(Self::Auto),
+            "format" => {
+                input.parse::<Token![=]>()?;
+                Ok(Self::Format(input.parse()?))
+            },
+            "name" => {
+                input.parse::<Token![=]>()?;
+                Ok(Self::Name(input.parse()?))
+            },
+            "last" => Ok(Self::Last),
+            _ => Err(syn::Error::new(name.span(), "expected one of: prev, upgrade, downgrade, auto, format, name, last")),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Args {
+    prev: Option<Type>,
+    upgrade: Option<syn::Path>,
+    downgrade: Option<syn::Path>,
+    auto: bool,
+    format: Option<Type>,
+    name: Option<LitStr>,
+    last: bool,
+}
+
+impl Args {
+    fn from_attrs(input: &DeriveInput) -> syn::Result<Self> {
+        let mut res = Self::default();
+        for attr in input.attrs.iter().filter(|attr| attr.path.is_ident("version")) {
+            let args = attr.parse_args_with(Punctuated::<Arg, Token![,]>::parse_terminated)?;
+            for arg in args {
+                match arg {
+                    Arg::Prev(ty) => res.prev = Some(ty),
+                    Arg::Upgrade(path) => res.upgrade = Some(path),
+                    Arg::Downgrade(path) => res.downgrade = Some(path),
+                    Arg::Auto => res.auto = true,
+                    Arg::Format(ty) => {
+                        res.auto = true;
+                        res.format = Some(ty);
+                    },
+                    Arg::Name(name) => res.name = Some(name),
+                    Arg::Last => res.last = true,
+                }
+            }
+        }
+        if res.prev.is_none() && (res.upgrade.is_some() || res.downgrade.is_some()) {
+            let msg = "first version can't be upgraded or downgraded, specify `prev`";
+            return Err(syn::Error::new_spanned(&input.ident, msg))
+        }
+        Ok(res)
+    }
+}
+
+/// Implements version chain traits, just like `chain!` does.
+///
+/// - `#[version(prev = Foo)]`: previous version in the chain. Without it type is the first version.
+/// - `#[version(upgrade = path::to::fn)]`: implements `Upgradeable` using `fn(Foo) -> Result<Self, Box<dyn Error>>`.
+/// - `#[version(downgrade = path::to::fn)]`: implements `Downgradeable` using `fn(Self) -> Result<Foo, Box<dyn Error>>`.
+/// - `#[version(auto)]`: implements `AutoSerde`.
+/// - `#[version(format = Json)]`: implements `AutoSerde` using the given format instead of the default one.
+/// - `#[version(name = "user.v1")]`: stable name used instead of `std::any::type_name`.
+/// - `#[version(last)]`: implements `LastVersion`. Rust can't find out that by itself.
+///
+/// Generic types are not supported: every version must be a concrete type.
+#[proc_macro_derive(Version, attributes(version))]
+pub fn derive_version(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match derive(&input) {
+        Ok(res) => res.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn derive(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let msg = "generic types can't be versions, every version must be a concrete type";
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(&input.generics, msg))
+    }
+    if let Some(clause) = &input.generics.where_clause {
+        return Err(syn::Error::new_spanned(clause, msg))
+    }
+    let args = Args::from_attrs(input)?;
+    let name = &input.ident;
+    let versions = quote!(::ttdb::versions);
+
+    let first = match &args.prev {
+        Some(prev) => quote!(<#prev as #versions::Version>::FirstVersion),
+        None => quote!(Self),
+    };
+    let mut res = quote! {
+        impl #versions::Version for #name {
+            type FirstVersion = #first;
+        }
+    };
+
+    if let Some(prev) = &args.prev {
+        res.extend(quote! {
+            impl #versions::PrevVersionRef for #name {
+                type PrevVersion = #prev;
+            }
+            impl #versions::NextVersionRef for #prev {
+                type NextVersion = #name;
+            }
+        });
+    }
+    if let (Some(prev), Some(upgrade)) = (&args.prev, &args.upgrade) {
+        res.extend(quote! {
+            impl #versions::Upgradeable for #name {
+                fn upgrade(prev: #prev) -> ::std::result::Result<Self, ::std::boxed::Box<dyn ::std::error::Error>> {
+                    #upgrade(prev)
+                }
+            }
+        });
+    }
+    if let (Some(prev), Some(downgrade)) = (&args.prev, &args.downgrade) {
+        res.extend(quote! {
+            impl #versions::Downgradeable for #name {
+                fn downgrade(self) -> ::std::result::Result<#prev, ::std::boxed::Box<dyn ::std::error::Error>> {
+                    #downgrade(self)
+                }
+            }
+        });
+    }
+    if args.auto {
+        let format = args.format.iter();
+        res.extend(quote! {
+            impl #versions::AutoSerde for #name {
+                #(type Format = #format;)*
+            }
+        });
+    }
+    if let Some(stable) = &args.name {
+        res.extend(quote! {
+            impl #versions::StableName for #name {
+                const STABLE_NAME: &'static str = #stable;
+            }
+        });
+    }
+    if args.last {
+        res.extend(quote! {
+            impl #versions::LastVersion for #name {}
+        });
+    }
+    Ok(res)
+}